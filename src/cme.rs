@@ -1,12 +1,57 @@
-use chrono::{DateTime, TimeZone, Utc};
+use chrono::{DateTime, Datelike, Days, NaiveDate, TimeZone, Utc, Weekday};
 use chrono_tz::{America::New_York, Tz};
+use plotly::{
+	Plot, Scatter,
+	common::Mode,
+	layout::{Axis, AxisSide},
+};
 use v_utils::prelude::*;
 
-static CFTC_CODE_BTC: u32 = 133741;
+static CFTC_CODE_BTC: &str = "133741";
+/// File under the share dir every fetched report is accumulated in
+static CFTC_HISTORY_FILE: &str = "cftc_history.json";
+/// Contracts compared on the cross-asset page, as (asset, CFTC code)
+pub static CFTC_CONTRACTS: [(&str, &str); 4] = [("BTC", CFTC_CODE_BTC), ("ETH", "146021"), ("S&P 500", "13874A"), ("Nasdaq-100", "209742")];
+/// Reports are published on Fridays at this time (America/New_York), unless a federal holiday delays them
+static RELEASE_TIME_ET: (u32, u32) = (15, 30);
+/// How many lines past the main block to look through for the concentration ratios
+static CONCENTRATION_SEARCH_DEPTH: usize = 10;
+/// COT Index at or beyond these is flagged as a positioning extreme
+static COT_EXTREME_BOUNDS: (f64, f64) = (10.0, 90.0);
+
+/// CFTC publishes the TFF report in two flavours, parsed by the same machinery
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReportKind {
+	#[default]
+	FuturesOnly,
+	FuturesAndOptions,
+}
+impl ReportKind {
+	pub const ALL: [Self; 2] = [Self::FuturesOnly, Self::FuturesAndOptions];
+
+	pub fn url(&self) -> &'static str {
+		match self {
+			Self::FuturesOnly => "https://www.cftc.gov/dea/futures/financial_lf.htm",
+			Self::FuturesAndOptions => "https://www.cftc.gov/dea/options/financial_lof.htm",
+		}
+	}
+
+	pub fn title(&self) -> &'static str {
+		match self {
+			Self::FuturesOnly => "Futures Only",
+			Self::FuturesAndOptions => "Futures and Options Combined",
+		}
+	}
+
+	/// Kind a report block's header line names, if it names one
+	fn from_header(line: &str) -> Option<Self> {
+		Self::ALL.into_iter().find(|kind| line.contains(kind.title()))
+	}
+}
 
 #[allow(unused)]
-#[derive(Clone, Debug, Default, derive_new::new, Copy)]
-struct PositionsInfo {
+#[derive(Clone, Debug, Default, derive_new::new, Copy, Serialize, Deserialize)]
+pub struct PositionsInfo {
 	current: f64,
 	change_since_last_week: f64,
 	percent_of_open: f64,
@@ -14,108 +59,174 @@ struct PositionsInfo {
 }
 
 #[allow(unused)]
-#[derive(Clone, Debug, Default, derive_new::new, Copy)]
-struct Positions {
+#[derive(Clone, Debug, Default, derive_new::new, Copy, Serialize, Deserialize)]
+pub struct Positions {
 	long: PositionsInfo,
 	short: PositionsInfo,
 	spreading: PositionsInfo,
 }
+impl Positions {
+	pub fn net(&self) -> f64 {
+		self.long.current - self.short.current
+	}
+
+	/// Net position as percent of open interest, comparable across contracts of different size
+	pub fn net_percent_of_open(&self) -> f64 {
+		self.long.percent_of_open - self.short.percent_of_open
+	}
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct ConcentrationRatio {
+	long: f64,
+	short: f64,
+}
+
+/// Percent of open interest held by the 4 and 8 largest traders, by gross and by net position
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Concentration {
+	gross_4: ConcentrationRatio,
+	gross_8: ConcentrationRatio,
+	net_4: ConcentrationRatio,
+	net_8: ConcentrationRatio,
+}
+impl Concentration {
+	/// Parses the section following the "Largest Traders" header, searching `lines` that come after the main block
+	pub fn parse(lines: &[String]) -> Result<Self> {
+		let header_pos = lines
+			.iter()
+			.take(CONCENTRATION_SEARCH_DEPTH)
+			.position(|line| line.contains("Largest Traders"))
+			.ok_or_else(|| eyre!("Could not find concentration ratios section"))?;
+		// first line below the header made up of exactly 8 numbers; the ones in between are column titles
+		let values: [f64; 8] = lines[header_pos + 1..]
+			.iter()
+			.take(CONCENTRATION_SEARCH_DEPTH)
+			.find_map(|line| {
+				let nums = line
+					.split(|c: char| c.is_whitespace() || c == ':')
+					.filter(|s| !s.is_empty())
+					.map(|s| s.parse::<f64>())
+					.collect::<std::result::Result<Vec<_>, _>>()
+					.ok()?;
+				nums.try_into().ok()
+			})
+			.ok_or_else(|| eyre!("Could not find concentration ratio values"))?;
+
+		let ratio = |i: usize| ConcentrationRatio { long: values[i], short: values[i + 1] };
+		Ok(Self {
+			gross_4: ratio(0),
+			gross_8: ratio(2),
+			net_4: ratio(4),
+			net_8: ratio(6),
+		})
+	}
+
+	fn ratios(&self) -> [(&'static str, &ConcentrationRatio); 4] {
+		[
+			("Gross, 4 or less", &self.gross_4),
+			("Gross, 8 or less", &self.gross_8),
+			("Net, 4 or less", &self.net_4),
+			("Net, 8 or less", &self.net_8),
+		]
+	}
+}
 
 #[allow(unused)]
-#[derive(Clone, Debug, Default, derive_new::new)]
-struct CftcReport {
+#[derive(Clone, Debug, Default, derive_new::new, Serialize, Deserialize)]
+pub struct CftcReport {
 	// pub asset: String,
+	pub kind: ReportKind,
 	pub date: DateTime<Utc>,
+	#[serde(rename = "dealer_intermediary")]
 	pub dealer_intermidiary: Positions,
 	pub asset_manager_or_institutional: Positions,
 	pub leveraged_funds: Positions,
 	pub other_reportables: Positions,
+	pub concentration: Option<Concentration>,
+	#[serde(skip)]
 	_non_reportables: Option<Value>,
 }
 impl CftcReport {
-	pub fn parse_by_index(page: &[String], index: u32) -> Result<Self> {
+	/// Parses the block of contract `index` off a page of `kind`, which is what the report is labeled with
+	pub fn parse_by_index(page: &[String], index: &str, kind: ReportKind) -> Result<Self> {
 		let index_line_pos = page
 			.iter()
 			.position(|line| line.contains(&format!("#{}", index)))
-			.ok_or_else(|| eyre!("Could not find the block with BTC index /*{CFTC_CODE_BTC}*/ in parsed CFTC report"))?;
-		let block: &[String; 20] = page
-			.get((index_line_pos - 8)..=(index_line_pos + 11))
+			.ok_or_else(|| eyre!("Could not find the block with index #{index} in parsed CFTC report"))?;
+		let block: &[String; 20] = index_line_pos
+			.checked_sub(8)
+			.and_then(|start| page.get(start..=(index_line_pos + 11)))
 			.and_then(|slice| slice.try_into().ok())
 			.ok_or_else(|| eyre!("Block size mismatch - expected 20 lines"))?;
 
-		block.try_into()
+		if let Some(header_kind) = ReportKind::from_header(&block[1])
+			&& header_kind != kind
+		{
+			bail!("Expected a {} report, but the header says {}: {}", kind.title(), header_kind.title(), block[1]);
+		}
+		let mut report: Self = block.try_into()?;
+		report.kind = kind;
+		let after_block = page.get(index_line_pos + 12..).unwrap_or_default();
+		report.concentration = Concentration::parse(after_block).inspect_err(|e| warn!("Failed to parse concentration ratios: {e}")).ok();
+		Ok(report)
 	}
 
-	pub fn to_markdown_table(&self) -> String {
+	/// Reportable trader categories, in the order CFTC lists them
+	fn categories(&self) -> [(&'static str, &Positions); 4] {
+		[
+			("Dealer Intermediary", &self.dealer_intermidiary),
+			("Asset Manager/Institutional", &self.asset_manager_or_institutional),
+			("Leveraged Funds", &self.leveraged_funds),
+			("Other Reportables", &self.other_reportables),
+		]
+	}
+
+	/// Rows of the full table: label and a Long/Short/Spread triplet of cells per category
+	fn rows(&self) -> [(&'static str, Vec<String>); 4] {
 		let format_num = |n: f64| format!("{:.0}", n);
 		let format_pct = |n: f64| format!("{:.1}", n);
 		let format_trader = |n: Option<u32>| n.map_or(".".to_string(), |v| v.to_string());
+		let cells = |f: &dyn Fn(&PositionsInfo) -> String| -> Vec<String> {
+			self.categories().iter().flat_map(|(_, p)| [f(&p.long), f(&p.short), f(&p.spreading)]).collect()
+		};
 
-		let date_str = self.date.format("%B %d, %Y").to_string();
-
-		format!(
-			"# Traders in Financial Futures - Futures Only Positions as of {}\n\n\
-           |Position Type|Dealer Intermediary|||Asset Manager/Institutional|||Leveraged Funds|||Other Reportables|||\n\
-           |------------|-------------------|---|---|----------------------|---|---|--------------|---|---|-----------------|---|---|\n\
-           ||Long|Short|Spread|Long|Short|Spread|Long|Short|Spread|Long|Short|Spread|\n\
-           |**Current**|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|\n\
-           |**Changes**|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|\n\
-           |**% of Open**|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|\n\
-           |**Traders**|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|",
-			date_str,
-			// Current positions
-			format_num(self.dealer_intermidiary.long.current),
-			format_num(self.dealer_intermidiary.short.current),
-			format_num(self.dealer_intermidiary.spreading.current),
-			format_num(self.asset_manager_or_institutional.long.current),
-			format_num(self.asset_manager_or_institutional.short.current),
-			format_num(self.asset_manager_or_institutional.spreading.current),
-			format_num(self.leveraged_funds.long.current),
-			format_num(self.leveraged_funds.short.current),
-			format_num(self.leveraged_funds.spreading.current),
-			format_num(self.other_reportables.long.current),
-			format_num(self.other_reportables.short.current),
-			format_num(self.other_reportables.spreading.current),
-			// Changes
-			format_num(self.dealer_intermidiary.long.change_since_last_week),
-			format_num(self.dealer_intermidiary.short.change_since_last_week),
-			format_num(self.dealer_intermidiary.spreading.change_since_last_week),
-			format_num(self.asset_manager_or_institutional.long.change_since_last_week),
-			format_num(self.asset_manager_or_institutional.short.change_since_last_week),
-			format_num(self.asset_manager_or_institutional.spreading.change_since_last_week),
-			format_num(self.leveraged_funds.long.change_since_last_week),
-			format_num(self.leveraged_funds.short.change_since_last_week),
-			format_num(self.leveraged_funds.spreading.change_since_last_week),
-			format_num(self.other_reportables.long.change_since_last_week),
-			format_num(self.other_reportables.short.change_since_last_week),
-			format_num(self.other_reportables.spreading.change_since_last_week),
-			// Percentages
-			format_pct(self.dealer_intermidiary.long.percent_of_open),
-			format_pct(self.dealer_intermidiary.short.percent_of_open),
-			format_pct(self.dealer_intermidiary.spreading.percent_of_open),
-			format_pct(self.asset_manager_or_institutional.long.percent_of_open),
-			format_pct(self.asset_manager_or_institutional.short.percent_of_open),
-			format_pct(self.asset_manager_or_institutional.spreading.percent_of_open),
-			format_pct(self.leveraged_funds.long.percent_of_open),
-			format_pct(self.leveraged_funds.short.percent_of_open),
-			format_pct(self.leveraged_funds.spreading.percent_of_open),
-			format_pct(self.other_reportables.long.percent_of_open),
-			format_pct(self.other_reportables.short.percent_of_open),
-			format_pct(self.other_reportables.spreading.percent_of_open),
-			// Traders
-			format_trader(self.dealer_intermidiary.long.number_of_traders),
-			format_trader(self.dealer_intermidiary.short.number_of_traders),
-			format_trader(self.dealer_intermidiary.spreading.number_of_traders),
-			format_trader(self.asset_manager_or_institutional.long.number_of_traders),
-			format_trader(self.asset_manager_or_institutional.short.number_of_traders),
-			format_trader(self.asset_manager_or_institutional.spreading.number_of_traders),
-			format_trader(self.leveraged_funds.long.number_of_traders),
-			format_trader(self.leveraged_funds.short.number_of_traders),
-			format_trader(self.leveraged_funds.spreading.number_of_traders),
-			format_trader(self.other_reportables.long.number_of_traders),
-			format_trader(self.other_reportables.short.number_of_traders),
-			format_trader(self.other_reportables.spreading.number_of_traders),
-		)
+		[
+			("Current", cells(&|i| format_num(i.current))),
+			("Changes", cells(&|i| format_num(i.change_since_last_week))),
+			("% of Open", cells(&|i| format_pct(i.percent_of_open))),
+			("Traders", cells(&|i| format_trader(i.number_of_traders))),
+		]
+	}
+
+	pub fn to_html_table(&self) -> String {
+		let mut s = format!(
+			"<table class=\"cftc\">\n<caption>Traders in Financial Futures - {} Positions as of {}</caption>\n<tr><th rowspan=\"2\">Position Type</th>",
+			self.kind.title(),
+			self.date.format("%B %d, %Y")
+		);
+		for (name, _) in self.categories() {
+			s.push_str(&format!("<th colspan=\"3\">{name}</th>"));
+		}
+		s.push_str(&format!("</tr>\n<tr>{}</tr>\n", "<th>Long</th><th>Short</th><th>Spread</th>".repeat(4)));
+		for (label, cells) in self.rows() {
+			let cells = cells.iter().map(|c| format!("<td>{c}</td>")).collect::<String>();
+			s.push_str(&format!("<tr><th>{label}</th>{cells}</tr>\n"));
+		}
+		s.push_str("</table>");
+		s
+	}
+
+	pub fn to_markdown_table(&self) -> String {
+		let mut s = format!("# Traders in Financial Futures - {} Positions as of {}\n\n|Position Type|", self.kind.title(), self.date.format("%B %d, %Y"));
+		for (name, _) in self.categories() {
+			s.push_str(&format!("{name}|||"));
+		}
+		s.push_str(&format!("\n|---|{}\n||{}", "---|---|---|".repeat(4), "Long|Short|Spread|".repeat(4)));
+		for (label, cells) in self.rows() {
+			s.push_str(&format!("\n|**{label}**|{}|", cells.join("|")));
+		}
+		s
 	}
 }
 impl TryFrom<&[String; 20]> for CftcReport {
@@ -167,11 +278,14 @@ impl TryFrom<&[String; 20]> for CftcReport {
 		}
 
 		Ok(CftcReport {
+			// the block alone doesn't say which page it came from, `parse_by_index` sets it
+			kind: ReportKind::default(),
 			date,
 			dealer_intermidiary: create_positions(0, &positions, &changes, &percents, traders)?,
 			asset_manager_or_institutional: create_positions(3, &positions, &changes, &percents, traders)?,
 			leveraged_funds: create_positions(6, &positions, &changes, &percents, traders)?,
 			other_reportables: create_positions(9, &positions, &changes, &percents, traders)?,
+			concentration: None,
 			_non_reportables: None,
 		})
 	}
@@ -191,35 +305,372 @@ impl PrettyPrint for DirectionalPositionsChange {
 	}
 }
 
-pub async fn fetch_cftc_positions() -> Result<String> {
-	let url = "https://www.cftc.gov/dea/futures/financial_lf.htm";
-	let response = reqwest::get(url).await?.text().await?;
-	let lines: Vec<String> = response.lines().map(String::from).collect();
-
-	let report = CftcReport::parse_by_index(&lines, CFTC_CODE_BTC)?;
-	let institutional_change = {
+impl DirectionalPositionsChange {
+	fn from_report(report: &CftcReport) -> [Self; 2] {
 		let institutional = &report.asset_manager_or_institutional;
-		DirectionalPositionsChange {
-			name: "Institutional",
-			long: NowThen::from_now_diff(institutional.long.current, institutional.long.change_since_last_week),
-			short: NowThen::from_now_diff(institutional.short.current, institutional.short.change_since_last_week),
+		let funds = &report.leveraged_funds;
+		[
+			DirectionalPositionsChange {
+				name: "Institutional",
+				long: NowThen::from_now_diff(institutional.long.current, institutional.long.change_since_last_week),
+				short: NowThen::from_now_diff(institutional.short.current, institutional.short.change_since_last_week),
+			},
+			DirectionalPositionsChange {
+				name: "Hedgefunds",
+				long: NowThen::from_now_diff(funds.long.current, funds.long.change_since_last_week),
+				short: NowThen::from_now_diff(funds.short.current, funds.short.change_since_last_week),
+			},
+		]
+	}
+}
+
+async fn fetch_page(kind: ReportKind) -> Result<Vec<String>> {
+	let response = reqwest::get(kind.url()).await?.text().await?;
+	Ok(response.lines().map(String::from).collect())
+}
+
+pub async fn fetch_report(kind: ReportKind) -> Result<CftcReport> {
+	let lines = fetch_page(kind).await?;
+	CftcReport::parse_by_index(&lines, CFTC_CODE_BTC, kind)
+}
+
+/// Reports of every contract in [CFTC_CONTRACTS] that could be found on the `lines` of a `kind` page, by asset
+pub fn parse_contracts(lines: &[String], kind: ReportKind) -> Vec<(&'static str, CftcReport)> {
+	CFTC_CONTRACTS
+		.iter()
+		.filter_map(|(asset, code)| match CftcReport::parse_by_index(lines, code, kind) {
+			Ok(report) => Some((*asset, report)),
+			Err(e) => {
+				warn!("Failed to parse CFTC report for {asset}: {e}");
+				None
+			}
+		})
+		.collect()
+}
+
+/// Net percent of open interest of each category, with assets side by side
+pub fn cross_asset_html_table(contracts: &[(&str, CftcReport)]) -> String {
+	let mut s = String::from("<table class=\"cftc\">\n<caption>Net positioning, % of open interest (long - short)</caption>\n<tr><th>Category</th>");
+	for (asset, report) in contracts {
+		s.push_str(&format!("<th>{asset}<br>{}</th>", report.date.format("%Y-%m-%d")));
+	}
+	s.push_str("</tr>\n");
+
+	let names = CftcReport::default().categories().map(|(name, _)| name);
+	for (i, name) in names.into_iter().enumerate() {
+		s.push_str(&format!("<tr><th>{name}</th>"));
+		for (_, report) in contracts {
+			let net = report.categories()[i].1.net_percent_of_open();
+			let color = if net >= 0.0 { "#4CAF50" } else { "#f44336" };
+			s.push_str(&format!("<td style=\"color: {color}\">{net:+.1}</td>"));
+		}
+		s.push_str("</tr>\n");
+	}
+	s.push_str("</table>");
+	s
+}
+
+/// Every report fetched so far, sorted by date, at most one per kind and date
+#[derive(Clone, Debug, Default, derive_more::Deref, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CftcHistory {
+	v: Vec<CftcReport>,
+}
+impl CftcHistory {
+	/// Reports accumulated by previous runs, or none if there is no history file yet.
+	///
+	/// A file that can't be parsed is moved aside to `<name>.unreadable-<timestamp>`, so `save` doesn't overwrite it; if that fails, so does this.
+	pub fn load() -> Result<Self> {
+		let path = share_dir!().join(CFTC_HISTORY_FILE);
+		let json = match std::fs::read_to_string(&path) {
+			Ok(json) => json,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+			Err(e) => bail!("Failed to read CFTC history at {}: {e}", path.display()),
+		};
+		let mut history: Self = match serde_json::from_str(&json) {
+			Ok(history) => history,
+			Err(e) => {
+				let aside = path.with_extension(format!("json.unreadable-{}", Utc::now().format("%Y%m%dT%H%M%S")));
+				std::fs::rename(&path, &aside).map_err(|rename_e| eyre!("Unreadable CFTC history at {} ({e}) couldn't be moved aside: {rename_e}", path.display()))?;
+				warn!("Moved unreadable CFTC history to {}: {e}", aside.display());
+				Self::default()
+			}
+		};
+		history.v.sort_by_key(|r| r.date);
+		Ok(history)
+	}
+
+	pub fn save(&self) -> Result<()> {
+		let json = serde_json::to_string_pretty(self)?;
+		std::fs::write(share_dir!().join(CFTC_HISTORY_FILE), json)?;
+		Ok(())
+	}
+
+	pub fn push(&mut self, report: CftcReport) {
+		match self.v.iter_mut().find(|r| r.kind == report.kind && r.date == report.date) {
+			Some(existing) => *existing = report,
+			None => {
+				self.v.push(report);
+				self.v.sort_by_key(|r| r.date);
+			}
+		}
+	}
+
+	pub fn series(&self, kind: ReportKind) -> impl Iterator<Item = &CftcReport> {
+		self.v.iter().filter(move |r| r.kind == kind)
+	}
+
+	/// Most recent report of each kind that has one
+	pub fn latest(&self) -> Vec<&CftcReport> {
+		ReportKind::ALL.into_iter().filter_map(|kind| self.series(kind).last()).collect()
+	}
+}
+
+/// Net long (long - short) of each trader category over the stored history
+pub fn plot_net_positions(history: &CftcHistory, kind: ReportKind) -> Plot {
+	let reports = history.series(kind).collect::<Vec<_>>();
+	let x_values: Vec<String> = reports.iter().map(|r| r.date.to_rfc3339()).collect();
+
+	let mut plot = Plot::new();
+	let title = format!("CME BTC net positioning, {} ({} reports)", kind.title(), reports.len());
+	plot.set_layout(plotly::Layout::new().title(title));
+
+	let names = CftcReport::default().categories().map(|(name, _)| name);
+	for (i, name) in names.into_iter().enumerate() {
+		let y_values: Vec<f64> = reports.iter().map(|r| r.categories()[i].1.net()).collect();
+		let trace = Scatter::new(x_values.clone(), y_values).mode(Mode::LinesMarkers).name(name);
+		plot.add_trace(trace);
+	}
+
+	plot
+}
+
+/// Full tables of `reports` as one CSV, a row per report and position type
+pub fn reports_to_csv(reports: &[&CftcReport]) -> String {
+	let mut header = vec!["Report".to_owned(), "As Of".to_owned(), "Position Type".to_owned()];
+	for (name, _) in CftcReport::default().categories() {
+		header.extend(["Long", "Short", "Spread"].map(|direction| format!("{name} {direction}")));
+	}
+
+	let mut lines = vec![header.join(",")];
+	for report in reports {
+		for (label, cells) in report.rows() {
+			lines.push(format!("{},{},{label},{}", report.kind.title(), report.date.format("%Y-%m-%d"), cells.join(",")));
+		}
+	}
+	lines.join("\n")
+}
+
+/// Asset manager and leveraged fund net positions against BTC price, sampled at each report's as-of date
+///
+/// `daily_closes` are (open time, close) of daily candles; the one opening on the as-of day is matched to the report.
+pub fn plot_positioning_vs_price(history: &CftcHistory, kind: ReportKind, daily_closes: &[(DateTime<Utc>, f64)]) -> Plot {
+	let reports = history.series(kind).collect::<Vec<_>>();
+	let x_values: Vec<String> = reports.iter().map(|r| r.date.to_rfc3339()).collect();
+
+	let mut plot = Plot::new();
+	let title = format!("CME BTC positioning vs price, {}", kind.title());
+	let layout = plotly::Layout::new()
+		.title(title)
+		.y_axis(Axis::new().title("Net contracts"))
+		.y_axis2(Axis::new().title("BTC").overlaying("y").side(AxisSide::Right));
+	plot.set_layout(layout);
+
+	let nets = [
+		("Asset Manager/Institutional", reports.iter().map(|r| r.asset_manager_or_institutional.net()).collect::<Vec<_>>()),
+		("Leveraged Funds", reports.iter().map(|r| r.leveraged_funds.net()).collect()),
+	];
+	for (name, y_values) in nets {
+		plot.add_trace(Scatter::new(x_values.clone(), y_values).mode(Mode::LinesMarkers).name(name));
+	}
+
+	// as-of dates are Tuesdays in New York, so match on that calendar day rather than the UTC instant
+	let (price_x, price_y): (Vec<String>, Vec<f64>) = reports
+		.iter()
+		.filter_map(|r| {
+			let as_of_day = r.date.with_timezone(&New_York).date_naive();
+			let (_, close) = daily_closes.iter().find(|(open_time, _)| open_time.date_naive() == as_of_day)?;
+			Some((r.date.to_rfc3339(), *close))
+		})
+		.unzip();
+	let price = Scatter::new(price_x, price_y).mode(Mode::LinesMarkers).name("BTC").y_axis("y2");
+	plot.add_trace(price);
+
+	plot
+}
+
+/// COT Index of the last value in `nets`: percentile of it among the preceding nets within `lookback` reports, 0..=100.
+///
+/// `None` until `nets` covers the whole lookback, as a percentile over a few weeks flags everything as extreme.
+pub fn cot_index(nets: &[f64], lookback: usize) -> Option<f64> {
+	if lookback < 2 || nets.len() < lookback {
+		return None;
+	}
+	let window = &nets[nets.len() - lookback..];
+	let (current, preceding) = window.split_last()?;
+	let at_or_below = preceding.iter().filter(|n| *n <= current).count();
+	Some(100.0 * at_or_below as f64 / preceding.len() as f64)
+}
+
+/// COT Index of each category for every lookback (in weeks), and any extremes reached
+pub fn cot_index_markdown(history: &CftcHistory, kind: ReportKind, lookbacks: &[usize]) -> Option<String> {
+	let reports = history.series(kind).collect::<Vec<_>>();
+	let latest = reports.last()?;
+	if lookbacks.is_empty() {
+		return None;
+	}
+
+	let mut s = format!("# COT Index ({} weeks of history, `.` until a lookback is fully covered)\n\n|Category|", reports.len());
+	s.push_str(&lookbacks.iter().map(|w| format!("{w}w|")).collect::<String>());
+	s.push_str(&format!("\n|---|{}", "---|".repeat(lookbacks.len())));
+
+	let mut extremes = Vec::new();
+	for (i, (name, _)) in latest.categories().into_iter().enumerate() {
+		let nets = reports.iter().map(|r| r.categories()[i].1.net()).collect::<Vec<_>>();
+		s.push_str(&format!("\n|{name}|"));
+		for &lookback in lookbacks {
+			let index = cot_index(&nets, lookback);
+			match index {
+				Some(index) => {
+					s.push_str(&format!("{index:.0}|"));
+					if index <= COT_EXTREME_BOUNDS.0 {
+						extremes.push(format!("- {name}: {lookback}w extreme short ({index:.0})"));
+					} else if index >= COT_EXTREME_BOUNDS.1 {
+						extremes.push(format!("- {name}: {lookback}w extreme long ({index:.0})"));
+					}
+				}
+				None => s.push_str(".|"),
+			}
+		}
+	}
+	if !extremes.is_empty() {
+		s.push_str(&format!("\n\nExtremes:\n{}", extremes.join("\n")));
+	}
+
+	Some(s)
+}
+
+/// Concentration ratios of the latest report, with change in percentage points since the one before it
+pub fn concentration_markdown(history: &CftcHistory, kind: ReportKind) -> Option<String> {
+	let reports = history.series(kind).filter(|r| r.concentration.is_some()).collect::<Vec<_>>();
+	let (latest, preceding) = reports.split_last()?;
+	let current = latest.concentration.expect("filtered above");
+	let previous = preceding.last().and_then(|r| r.concentration);
+
+	let mut s = String::from("# Concentration (% of open interest held by the largest traders)\n\n|Traders|Long|Change|Short|Change|\n|---|---|---|---|---|");
+	for (i, (name, ratio)) in current.ratios().into_iter().enumerate() {
+		let change = |f: fn(&ConcentrationRatio) -> f64| previous.map_or(".".to_string(), |p| format!("{:+.1}", f(ratio) - f(p.ratios()[i].1)));
+		s.push_str(&format!("\n|{name}|{:.1}|{}|{:.1}|{}|", ratio.long, change(|r| r.long), ratio.short, change(|r| r.short)));
+	}
+	Some(s)
+}
+
+/// US federal holidays of `year`, on the days they are observed
+fn federal_holidays(year: i32) -> Vec<NaiveDate> {
+	let nth = |month: u32, weekday: Weekday, n: u8| NaiveDate::from_weekday_of_month_opt(year, month, weekday, n).expect("every month has 4 of each weekday");
+	// fixed-date holidays falling on a weekend are observed on the closest weekday
+	let observed = |month: u32, day: u32| {
+		let date = NaiveDate::from_ymd_opt(year, month, day).expect("valid date");
+		match date.weekday() {
+			Weekday::Sat => date - Days::new(1),
+			Weekday::Sun => date + Days::new(1),
+			_ => date,
 		}
 	};
-	let funds_change = {
-		let funds = &report.leveraged_funds;
-		DirectionalPositionsChange {
-			name: "Hedgefunds",
-			long: NowThen::from_now_diff(funds.long.current, funds.long.change_since_last_week),
-			short: NowThen::from_now_diff(funds.short.current, funds.short.change_since_last_week),
+	let last_monday_of_may = NaiveDate::from_weekday_of_month_opt(year, 5, Weekday::Mon, 5).unwrap_or_else(|| nth(5, Weekday::Mon, 4));
+
+	vec![
+		observed(1, 1),
+		nth(1, Weekday::Mon, 3),
+		nth(2, Weekday::Mon, 3),
+		last_monday_of_may,
+		observed(6, 19),
+		observed(7, 4),
+		nth(9, Weekday::Mon, 1),
+		nth(10, Weekday::Mon, 2),
+		observed(11, 11),
+		nth(11, Weekday::Thu, 4),
+		observed(12, 25),
+	]
+}
+
+/// When CFTC publishes the report with the given as-of date: Friday of that week, or the next business day after it if the week has a federal holiday
+pub fn release_of(as_of: DateTime<Utc>) -> DateTime<Utc> {
+	let as_of = as_of.with_timezone(&New_York).date_naive();
+	let monday = as_of - Days::new(as_of.weekday().num_days_from_monday() as u64);
+	let mut holidays = federal_holidays(monday.year());
+	holidays.extend(federal_holidays(monday.year() + 1));
+
+	let mut release = monday + Days::new(4);
+	if (0..5).any(|d| holidays.contains(&(monday + Days::new(d)))) {
+		release = release + Days::new(1);
+		while release.weekday().num_days_from_monday() >= 5 || holidays.contains(&release) {
+			release = release + Days::new(1);
 		}
+	}
+
+	let (hour, min) = RELEASE_TIME_ET;
+	let release = release.and_hms_opt(hour, min, 0).expect("valid time");
+	New_York
+		.from_local_datetime(&release)
+		.earliest()
+		.expect("15:30 is never skipped by DST transitions")
+		.with_timezone(&Utc)
+}
+
+/// Fetches the latest reports into `history`, returning the summary of them
+/// What a successful [fetch_cftc_positions] yields besides the reports it pushes to the history
+#[derive(Clone, Debug, Default)]
+pub struct CftcUpdate {
+	/// Week-over-week changes of the BTC reports
+	pub summary: String,
+	/// Futures-only reports of every contract in [CFTC_CONTRACTS], parsed off the same page as BTC's
+	pub contracts: Vec<(&'static str, CftcReport)>,
+}
+
+pub async fn fetch_cftc_positions(history: &mut CftcHistory) -> Result<CftcUpdate> {
+	let (futures_only_page, combined) = futures::join!(fetch_page(ReportKind::FuturesOnly), fetch_report(ReportKind::FuturesAndOptions));
+	let futures_only_page = futures_only_page?;
+	let futures_only = CftcReport::parse_by_index(&futures_only_page, CFTC_CODE_BTC, ReportKind::FuturesOnly)?;
+	// options are the supplementary view, so don't lose the futures-only one over them
+	let combined = combined.inspect_err(|e| warn!("Failed to fetch {} CFTC report: {e}", ReportKind::FuturesAndOptions.title())).ok();
+	history.push(futures_only.clone());
+	if let Some(combined) = &combined {
+		history.push(combined.clone());
+	}
+
+	let column = |report: &CftcReport| -> String {
+		let changes = DirectionalPositionsChange::from_report(report);
+		format!("{}:\n- {}\n- {}", report.kind.title(), changes[0].to_string_pretty(2), changes[1].to_string_pretty(2))
 	};
+	let columns = match &combined {
+		Some(combined) => side_by_side(&[&column(&futures_only), &column(combined)], 4),
+		None => column(&futures_only),
+	};
+
+	Ok(CftcUpdate {
+		summary: format!("CME positions as of {}:\n{columns}", futures_only.date),
+		contracts: parse_contracts(&futures_only_page, ReportKind::FuturesOnly),
+	})
+}
+
+/// Lays multi-line blocks out as columns, each padded to the width of its longest line
+fn side_by_side(blocks: &[&str], gap: usize) -> String {
+	let columns: Vec<Vec<&str>> = blocks.iter().map(|b| b.lines().collect()).collect();
+	let widths: Vec<usize> = columns.iter().map(|c| c.iter().map(|l| l.chars().count()).max().unwrap_or(0)).collect();
+	let height = columns.iter().map(|c| c.len()).max().unwrap_or(0);
 
-	Ok(format!(
-		"CME positions as of {}:\n- {}\n- {}",
-		report.date,
-		institutional_change.to_string_pretty(2),
-		funds_change.to_string_pretty(2)
-	))
+	(0..height)
+		.map(|i| {
+			let row = columns
+				.iter()
+				.zip(&widths)
+				.map(|(column, width)| format!("{:<width$}", column.get(i).copied().unwrap_or(""), width = width + gap))
+				.collect::<String>();
+			row.trim_end().to_owned()
+		})
+		.collect::<Vec<_>>()
+		.join("\n")
 }
 
 #[allow(unused)]
@@ -229,8 +680,96 @@ pub async fn fetch_cftc_positions() -> Result<String> {
 async fn main() {
 	clientside!();
 
-	match fetch_cftc_positions().await {
-		Ok(result) => println!("{}", result),
+	match fetch_cftc_positions(&mut CftcHistory::default()).await {
+		Ok(update) => println!("{}", update.summary),
 		Err(e) => eprintln!("Error: {}", e),
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+		NaiveDate::from_ymd_opt(y, m, d).unwrap()
+	}
+
+	/// As-of dates are Tuesdays, stamped at release time ET
+	fn as_of(y: i32, m: u32, d: u32) -> DateTime<Utc> {
+		New_York.from_local_datetime(&date(y, m, d).and_hms_opt(15, 30, 0).unwrap()).earliest().unwrap().with_timezone(&Utc)
+	}
+
+	#[test]
+	fn cot_index_needs_full_lookback() {
+		assert_eq!(cot_index(&[1.0, 2.0], 26), None);
+		assert_eq!(cot_index(&[1.0; 25], 26), None);
+		assert!(cot_index(&[1.0; 26], 26).is_some());
+		assert_eq!(cot_index(&[1.0], 1), None);
+	}
+
+	#[test]
+	fn cot_index_is_percentile_within_lookback() {
+		// only the last 5 count: 10 is above all 4 preceding
+		assert_eq!(cot_index(&[100.0, 1.0, 2.0, 3.0, 4.0, 10.0], 5), Some(100.0));
+		assert_eq!(cot_index(&[1.0, 2.0, 3.0, 4.0, 0.0], 5), Some(0.0));
+		assert_eq!(cot_index(&[1.0, 2.0, 3.0, 4.0, 2.5], 5), Some(50.0));
+		// ties count as at or below
+		assert_eq!(cot_index(&[2.0, 2.0, 2.0, 2.0, 2.0], 5), Some(100.0));
+	}
+
+	#[test]
+	fn concentration_parses_largest_traders_section() {
+		// verbatim from the lines following a contract's block in financial_lf.htm
+		let section = r#"-----------------------------------------------------------------------------------------------------------------------------------------------------------
+                                                        Percent of Open Interest Represented by the Indicated Number of the Largest Traders
+                                                              By Gross Position                                     By Net Position
+                                                   4 or Less Traders       8 or Less Traders           4 or Less Traders       8 or Less Traders
+                                                   Long:       Short       Long        Short:          Long        Short       Long        Short
+                                                   ----------------------------------------------------------------------------------------------
+                                                   38.8        51.4        56.2        63.2            36.9        47.1        51.9        57.6"#;
+		let lines = section.lines().map(str::to_owned).collect::<Vec<_>>();
+		let c = Concentration::parse(&lines).unwrap();
+		let values = [c.gross_4, c.gross_8, c.net_4, c.net_8].iter().flat_map(|r| [r.long, r.short]).collect::<Vec<_>>();
+		assert_eq!(values, [38.8, 51.4, 56.2, 63.2, 36.9, 47.1, 51.9, 57.6]);
+	}
+
+	#[test]
+	fn weekend_holidays_observed_on_closest_weekday() {
+		// July 4th 2026 is a Saturday, New Year's Day 2023 a Sunday
+		assert!(federal_holidays(2026).contains(&date(2026, 7, 3)));
+		assert!(federal_holidays(2023).contains(&date(2023, 1, 2)));
+	}
+
+	#[test]
+	fn new_years_day_observed_in_previous_year() {
+		// January 1st 2022 is a Saturday
+		assert!(federal_holidays(2022).contains(&date(2021, 12, 31)));
+	}
+
+	#[test]
+	fn memorial_day_is_last_monday_of_may() {
+		// May 2021 has five Mondays, May 2024 four
+		assert!(federal_holidays(2021).contains(&date(2021, 5, 31)));
+		assert!(federal_holidays(2024).contains(&date(2024, 5, 27)));
+	}
+
+	#[test]
+	fn ordinary_week_releases_on_friday() {
+		assert_eq!(release_of(as_of(2024, 3, 12)), Utc.with_ymd_and_hms(2024, 3, 15, 19, 30, 0).unwrap());
+	}
+
+	#[test]
+	fn thanksgiving_week_releases_on_monday() {
+		assert_eq!(release_of(as_of(2024, 11, 26)), Utc.with_ymd_and_hms(2024, 12, 2, 20, 30, 0).unwrap());
+	}
+
+	#[test]
+	fn christmas_week_releases_on_monday() {
+		assert_eq!(release_of(as_of(2024, 12, 24)), Utc.with_ymd_and_hms(2024, 12, 30, 20, 30, 0).unwrap());
+	}
+
+	#[test]
+	fn new_years_week_uses_next_years_holidays() {
+		assert_eq!(release_of(as_of(2024, 12, 31)), Utc.with_ymd_and_hms(2025, 1, 6, 20, 30, 0).unwrap());
+	}
+}
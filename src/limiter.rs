@@ -0,0 +1,106 @@
+use tokio::{
+	sync::{Mutex, Semaphore, SemaphorePermit},
+	time::{Duration, Instant},
+};
+use v_exchanges::AbsMarket;
+use v_utils::prelude::*;
+
+/// Binance futures `klines` cost for limits in 500..=1000, the most any of our requests ask for
+pub const KLINES_WEIGHT: u32 = 5;
+/// `futures/data` endpoints (long/short ratios, open interest history), limited by request count rather than weight
+pub const FUTURES_DATA_WEIGHT: u32 = 1;
+const MINUTE: Duration = Duration::from_secs(60);
+/// Binance counts `futures/data` requests over 5 minutes
+const FUTURES_DATA_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Shared by every fetch hitting one set of exchange limits: caps requests in flight and the request weight spent per window
+#[derive(Debug)]
+pub struct RequestLimiter {
+	in_flight: Semaphore,
+	budget: u32,
+	window: Duration,
+	/// What a `klines` request costs against `budget`
+	klines_weight: u32,
+	/// Start of the current window and weight spent in it
+	spent: Mutex<(Instant, u32)>,
+}
+impl RequestLimiter {
+	pub fn new(name: &str, max_in_flight: usize, budget: u32, window: Duration, klines_weight: u32) -> Self {
+		info!("{name} requests limited to {max_in_flight} in flight and {budget} weight per {window:?}");
+		Self {
+			in_flight: Semaphore::new(max_in_flight.max(1)),
+			budget,
+			window,
+			klines_weight,
+			spent: Mutex::new((Instant::now(), 0)),
+		}
+	}
+
+	pub fn klines_weight(&self) -> u32 {
+		self.klines_weight
+	}
+
+	/// Waits for a free slot and for `weight` to fit into this window's budget. Hold the permit for the duration of the request.
+	pub async fn acquire(&self, weight: u32) -> SemaphorePermit<'_> {
+		let permit = self.in_flight.acquire().await.expect("semaphore is never closed");
+		loop {
+			let mut window = self.spent.lock().await;
+			let (started, spent) = &mut *window;
+			if started.elapsed() >= self.window {
+				*started = Instant::now();
+				*spent = 0;
+			}
+			// a single request heavier than the whole budget still has to go through at some point
+			if *spent + weight <= self.budget || *spent == 0 {
+				*spent += weight;
+				return permit;
+			}
+
+			let wait = self.window.saturating_sub(started.elapsed());
+			info!("Spent {}/{} request weight this window, waiting {wait:?}", spent, self.budget);
+			drop(window);
+			tokio::time::sleep(wait).await;
+		}
+	}
+
+	/// Awaits `request` once it's allowed through
+	pub async fn run<F: Future>(&self, weight: u32, request: F) -> F::Output {
+		let _permit = self.acquire(weight).await;
+		request.await
+	}
+}
+
+/// A [RequestLimiter] per set of exchange limits, so requests to one don't spend another's budget
+#[derive(Debug)]
+pub struct Limiters {
+	pub binance_futures: RequestLimiter,
+	/// Binance futures `futures/data` endpoints, which have a request budget of their own
+	pub binance_futures_data: RequestLimiter,
+	pub binance_spot: RequestLimiter,
+	pub bybit: RequestLimiter,
+	/// Any other market, until it's given limits of its own
+	pub other: RequestLimiter,
+}
+impl Limiters {
+	pub fn new(max_in_flight: usize, binance_futures_weight: u32, binance_futures_data_requests: u32, binance_spot_weight: u32, bybit_requests: u32) -> Self {
+		Self {
+			binance_futures: RequestLimiter::new("Binance futures", max_in_flight, binance_futures_weight, MINUTE, KLINES_WEIGHT),
+			binance_futures_data: RequestLimiter::new("Binance futures data", max_in_flight, binance_futures_data_requests, FUTURES_DATA_WINDOW, FUTURES_DATA_WEIGHT),
+			binance_spot: RequestLimiter::new("Binance spot", max_in_flight, binance_spot_weight, MINUTE, KLINES_WEIGHT),
+			// Bybit limits by request count, so every request is weighed as one
+			bybit: RequestLimiter::new("Bybit", max_in_flight, bybit_requests, MINUTE, 1),
+			other: RequestLimiter::new("Other exchange", max_in_flight, binance_futures_weight, MINUTE, KLINES_WEIGHT),
+		}
+	}
+
+	/// The limiter whose budget requests to `market` count against
+	pub fn for_market(&self, market: AbsMarket) -> &RequestLimiter {
+		let market = market.to_string();
+		match market.split_once('/') {
+			Some(("Binance", "Futures")) => &self.binance_futures,
+			Some(("Binance", _)) => &self.binance_spot,
+			Some(("Bybit", _)) => &self.bybit,
+			_ => &self.other,
+		}
+	}
+}
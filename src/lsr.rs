@@ -1,39 +1,474 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
 use futures::future::join_all;
+use plotly::{
+	Plot, Scatter,
+	common::Mode,
+	layout::{Axis, AxisSide},
+};
 use v_exchanges::{
 	binance::{self, data::Lsrs},
+	bybit,
 	prelude::*,
 };
 use v_utils::prelude::*;
 
-use crate::Mock;
+use crate::{
+	Mock,
+	limiter::{FUTURES_DATA_WEIGHT, Limiters, RequestLimiter},
+	market_structure::RelevantHistoricalData,
+};
 
 const SLICE_SIZE: usize = 10;
+/// Pairs with no data are retried after this many days, doubling on each consecutive empty response
+const NO_DATA_BASE_RETRY_DAYS: i64 = 1;
+/// Ceiling of the backoff, so pairs Binance starts supporting are picked up eventually
+const NO_DATA_MAX_RETRY_DAYS: i64 = 30;
+/// Plain list of no-data pairs in the share dir, which the schedule replaced
+const LEGACY_NO_DATA_FILE: &str = "lsr_no_data_pairs";
+/// Fewer stored values than this and a pair's z-score isn't meaningful yet
+const Z_SCORE_MIN_SAMPLES: usize = 48;
+/// Nor until the stored values go back this many days, however dense they are
+const Z_SCORE_MIN_SPAN_DAYS: i64 = 7;
+/// How far back per-pair history is kept, the "full month" searching for a specific pair displays
+const HISTORY_RETENTION_DAYS: i64 = 30;
+/// Separates the columns of different sources
+const COLUMN_GAP: &str = "  ";
+
+/// Whose positioning a long/short ratio is computed over
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum LsrSource {
+	GlobalAccounts,
+	TopAccounts,
+	TopPositions,
+}
+impl LsrSource {
+	pub const ALL: [Self; 3] = [Self::GlobalAccounts, Self::TopAccounts, Self::TopPositions];
+
+	pub fn title(&self) -> &'static str {
+		match self {
+			Self::GlobalAccounts => "Global Accounts",
+			Self::TopAccounts => "Top Trader Accounts",
+			Self::TopPositions => "Top Trader Positions",
+		}
+	}
+
+	fn who(&self) -> &'static str {
+		match self {
+			Self::GlobalAccounts => "Global",
+			Self::TopAccounts => "TopAccounts",
+			Self::TopPositions => "TopPositions",
+		}
+	}
+}
+
+/// How pairs are ordered into most shorted and most longed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LsrRanking {
+	/// Latest long share
+	#[default]
+	Level,
+	/// Latest long share relative to the pair's own stored history
+	ZScore,
+}
+
+/// What each pair's long share counts for in a market-wide average
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LsrWeighting {
+	Equal,
+	QuoteVolume,
+	OpenInterest,
+}
+impl LsrWeighting {
+	pub const ALL: [Self; 3] = [Self::Equal, Self::QuoteVolume, Self::OpenInterest];
+
+	pub fn title(&self) -> &'static str {
+		match self {
+			Self::Equal => "equal-weighted",
+			Self::QuoteVolume => "volume-weighted",
+			Self::OpenInterest => "OI-weighted",
+		}
+	}
+}
+
+/// One pair's latest ratio from one source
+#[derive(Clone, Debug, Serialize)]
+pub struct PairLsrSummary {
+	pub pair: String,
+	pub source: LsrSource,
+	/// Latest long share
+	pub long: f64,
+	/// Of long share, over the fetched range
+	pub change: f64,
+	/// Of the latest long share within the pair's stored history
+	pub z_score: Option<f64>,
+	/// Position among all pairs of the source under the requested ranking, 1 being the most shorted
+	pub rank: usize,
+	pub of: usize,
+}
+
+/// Column of the full LSR table
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LsrColumn {
+	Pair,
+	#[default]
+	Long,
+	Change,
+	ZScore,
+	PriceChange,
+}
+impl LsrColumn {
+	pub const ALL: [Self; 5] = [Self::Pair, Self::Long, Self::Change, Self::ZScore, Self::PriceChange];
+
+	pub fn title(&self) -> &'static str {
+		match self {
+			Self::Pair => "Pair",
+			Self::Long => "Long share",
+			Self::Change => "Change",
+			Self::ZScore => "Z-score",
+			Self::PriceChange => "24h price change (%)",
+		}
+	}
+}
+
+/// One pair's row of the full LSR table
+#[derive(Clone, Debug, Serialize)]
+pub struct LsrTableRow {
+	pub pair: String,
+	/// Latest long share
+	pub long: f64,
+	/// Of long share, over the fetched range
+	pub change: f64,
+	pub z_score: Option<f64>,
+	/// In percent
+	pub price_change: Option<f64>,
+}
+impl LsrTableRow {
+	/// Numeric value of `column`, `None` for [LsrColumn::Pair] and missing data
+	pub fn value(&self, column: LsrColumn) -> Option<f64> {
+		match column {
+			LsrColumn::Pair => None,
+			LsrColumn::Long => Some(self.long),
+			LsrColumn::Change => Some(self.change),
+			LsrColumn::ZScore => self.z_score,
+			LsrColumn::PriceChange => self.price_change,
+		}
+	}
+}
+
+/// Price and long share of a pair moving against each other over the range
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, derive_more::Display)]
+pub enum DivergenceKind {
+	/// Price rising while long share falls
+	#[display("shorts squeezed")]
+	ShortsSqueezed,
+	/// Price falling while long share rises
+	#[display("longs piling in")]
+	LongsPilingIn,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Divergence {
+	pub pair: String,
+	pub kind: DivergenceKind,
+	/// ln(last / first) of close, over the span the long share covers
+	pub price_change: f64,
+	/// Of global accounts long share, over the range
+	pub long_change: f64,
+	/// Product of both changes, each in cross-sectional standard deviations. Higher is stronger.
+	pub strength: f64,
+}
+
+/// Exchanges long/short ratios are collected from. Binance is the primary one, the only one top-trader sources are fetched from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, derive_more::Display)]
+pub enum Venue {
+	Binance,
+	Bybit,
+}
+
+/// Everything one refresh collects: ratios of every pair that had data, per source
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LsrData {
+	pub market: String,
+	pub pairs_len: usize,
+	/// Binance ratios, by source
+	pub sources: Vec<(LsrSource, SortedLsrs)>,
+	/// Global accounts ratios of the venues other than Binance
+	pub other_venues: Vec<(Venue, SortedLsrs)>,
+	/// Latest open interest in base asset, by venue and pair
+	pub open_interest: Vec<(Venue, BTreeMap<String, f64>)>,
+	/// Z-score of each pair's latest long share within its own stored history, by source
+	#[serde(default)]
+	pub z_scores: Vec<(LsrSource, BTreeMap<String, f64>)>,
+	/// How far back the longest history any z-score was computed over goes, in days
+	#[serde(default)]
+	pub z_score_span_days: Option<i64>,
+	/// Candles of each pair over the same range, taken from the market-structure refresh of the same market rather than fetched again.
+	/// Empty until that refresh has run, and with it volume/OI weighting, divergences and price changes.
+	#[serde(default)]
+	pub candles: BTreeMap<String, RelevantHistoricalData>,
+}
+impl LsrData {
+	/// Weight of each global accounts pair, those the weighting has no data for left out
+	fn weights(&self, weighting: LsrWeighting) -> BTreeMap<String, f64> {
+		let Some(global) = self.get(LsrSource::GlobalAccounts) else {
+			return BTreeMap::new();
+		};
+		let binance_oi = self.open_interest.iter().find(|(venue, _)| *venue == Venue::Binance).map(|(_, oi)| oi);
+		global
+			.iter()
+			.filter_map(|lsrs| {
+				let pair = lsrs.pair.to_string();
+				let weight = match weighting {
+					LsrWeighting::Equal => 1.0,
+					LsrWeighting::QuoteVolume => self.candles.get(&pair)?.col_volumes.iter().sum(),
+					// open interest is in base asset, so price it to compare across pairs
+					LsrWeighting::OpenInterest => binance_oi?.get(&pair)? * self.candles.get(&pair)?.col_closes.last()?,
+				};
+				Some((pair, weight))
+			})
+			.collect()
+	}
+
+	/// Market-wide latest long share of global accounts
+	pub fn weighted_average(&self, weighting: LsrWeighting) -> Option<f64> {
+		self.weighted_series(weighting).last().map(|(_, long)| *long)
+	}
+
+	/// Market-wide long share of global accounts at every timestamp of the fetched range
+	pub fn weighted_series(&self, weighting: LsrWeighting) -> Vec<(DateTime<Utc>, f64)> {
+		let Some(global) = self.get(LsrSource::GlobalAccounts) else {
+			return Vec::new();
+		};
+		let weights = self.weights(weighting);
+
+		let mut by_time: BTreeMap<DateTime<Utc>, (f64, f64)> = BTreeMap::new();
+		for lsrs in global.iter() {
+			let Some(weight) = weights.get(&lsrs.pair.to_string()) else { continue };
+			for lsr in lsrs.iter() {
+				let (weighted_sum, total_weight) = by_time.entry(lsr.time).or_default();
+				*weighted_sum += lsr.long() * weight;
+				*total_weight += weight;
+			}
+		}
+		by_time
+			.into_iter()
+			.filter(|(_, (_, total_weight))| *total_weight > 0.0)
+			.map(|(time, (weighted_sum, total_weight))| (time, weighted_sum / total_weight))
+			.collect()
+	}
+
+	/// Pairs whose price and global accounts long share moved in opposite directions, strongest first
+	pub fn divergences(&self) -> Vec<Divergence> {
+		let Some(global) = self.get(LsrSource::GlobalAccounts) else {
+			return Vec::new();
+		};
+		let moves = global
+			.iter()
+			.filter_map(|lsrs| {
+				let pair = lsrs.pair.to_string();
+				let (start, end) = (lsrs.first()?.time, lsrs.last()?.time);
+				let candles = self.candles.get(&pair)?;
+				let mut closes = candles.col_open_times.iter().zip(&candles.col_closes).filter(|(time, _)| (start..=end).contains(*time)).map(|(_, close)| *close);
+				let first = closes.next()?;
+				let last = closes.last()?;
+				let price_change = (last / first).ln();
+				let long_change = change(lsrs);
+				(price_change.is_finite() && long_change.is_finite()).then_some((pair, price_change, long_change))
+			})
+			.collect::<Vec<_>>();
+
+		let std = |values: Vec<f64>| {
+			let n = values.len() as f64;
+			let mean = values.iter().sum::<f64>() / n;
+			(values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n).sqrt()
+		};
+		let price_std = std(moves.iter().map(|(_, price, _)| *price).collect());
+		let long_std = std(moves.iter().map(|(_, _, long)| *long).collect());
+		if !(price_std > 0.0 && long_std > 0.0) {
+			return Vec::new();
+		}
+
+		let mut divergences = moves
+			.into_iter()
+			.filter_map(|(pair, price_change, long_change)| {
+				let kind = match (price_change > 0.0, long_change > 0.0) {
+					(true, false) if long_change < 0.0 => DivergenceKind::ShortsSqueezed,
+					(false, true) if price_change < 0.0 => DivergenceKind::LongsPilingIn,
+					_ => return None,
+				};
+				Some(Divergence {
+					pair,
+					kind,
+					price_change,
+					long_change,
+					strength: -(price_change / price_std) * (long_change / long_std),
+				})
+			})
+			.collect::<Vec<_>>();
+		divergences.sort_by(|a, b| b.strength.total_cmp(&a.strength));
+		divergences
+	}
+
+	/// Percent change of the close over the last 24h of the fetched candles
+	pub fn price_change_24h(&self, pair: &str) -> Option<f64> {
+		let candles = self.candles.get(pair)?;
+		let (last_time, last) = candles.col_open_times.iter().zip(&candles.col_closes).last()?;
+		let (_, first) = candles.col_open_times.iter().zip(&candles.col_closes).find(|(time, _)| **time >= *last_time - chrono::Duration::hours(24))?;
+		let change = (last / first - 1.0) * 100.0;
+		change.is_finite().then_some(change)
+	}
+
+	/// Every pair of `source`, those with `pair_filter` in their name, sorted by `sort`. Rows missing the sorted value go last either way.
+	pub fn table(&self, source: LsrSource, sort: LsrColumn, descending: bool, pair_filter: &str) -> Vec<LsrTableRow> {
+		let Some(sorted) = self.get(source) else {
+			return Vec::new();
+		};
+		let pair_filter = pair_filter.to_uppercase();
+		let mut rows = sorted
+			.iter()
+			.filter(|lsrs| lsrs.pair.to_string().contains(&pair_filter))
+			.map(|lsrs| {
+				let pair = lsrs.pair.to_string();
+				LsrTableRow {
+					long: lsrs.last().unwrap().long(),
+					change: change(lsrs),
+					z_score: self.z_score(source, &pair),
+					price_change: self.price_change_24h(&pair),
+					pair,
+				}
+			})
+			.collect::<Vec<_>>();
+
+		rows.sort_by(|a, b| {
+			let ordering = match sort {
+				LsrColumn::Pair => a.pair.cmp(&b.pair),
+				_ => match (a.value(sort), b.value(sort)) {
+					(Some(a), Some(b)) => a.total_cmp(&b),
+					(Some(_), None) => return std::cmp::Ordering::Less,
+					(None, Some(_)) => return std::cmp::Ordering::Greater,
+					(None, None) => std::cmp::Ordering::Equal,
+				},
+			};
+			if descending { ordering.reverse() } else { ordering }
+		});
+		rows
+	}
 
-//Q: potentially fix to "1D", req and store full month of data for both Global and Top Positions, to display when searching for specific one.
-pub async fn get(tf: Timeframe, range: RequestRange) -> Result<String> {
+	pub fn get(&self, source: LsrSource) -> Option<&SortedLsrs> {
+		self.sources.iter().find(|(s, _)| *s == source).map(|(_, lsrs)| lsrs)
+	}
+
+	pub fn z_score(&self, source: LsrSource, pair: &str) -> Option<f64> {
+		self.z_scores.iter().find(|(s, _)| *s == source)?.1.get(pair).copied()
+	}
+
+	/// Binance sources ordered by `ranking`. Under [LsrRanking::ZScore] pairs without enough history are left out.
+	pub fn ranked_sources(&self, ranking: LsrRanking) -> Vec<(LsrSource, SortedLsrs)> {
+		self.sources
+			.iter()
+			.map(|(source, sorted)| {
+				let ranked = match ranking {
+					LsrRanking::Level => sorted.clone(),
+					LsrRanking::ZScore => sorted.by_z_score(|pair| self.z_score(*source, pair)),
+				};
+				(*source, ranked)
+			})
+			.collect()
+	}
+
+	/// Where `pair` stands in each source it has data for
+	pub fn lookup(&self, pair: &str, ranking: LsrRanking) -> Vec<PairLsrSummary> {
+		let pair = pair.to_uppercase();
+		self.ranked_sources(ranking)
+			.iter()
+			.filter_map(|(source, sorted)| {
+				let position = sorted.iter().position(|lsrs| lsrs.pair.to_string() == pair)?;
+				let lsrs = &sorted[position];
+				let long = lsrs.last()?.long();
+				Some(PairLsrSummary {
+					pair: pair.clone(),
+					source: *source,
+					long,
+					change: change(lsrs),
+					z_score: self.z_score(*source, &pair),
+					rank: position + 1,
+					of: sorted.len(),
+				})
+			})
+			.collect()
+	}
+
+	/// Global accounts ratios of every venue
+	pub fn by_venue(&self) -> Vec<(Venue, &SortedLsrs)> {
+		let binance = self.get(LsrSource::GlobalAccounts).map(|lsrs| (Venue::Binance, lsrs));
+		binance.into_iter().chain(self.other_venues.iter().map(|(venue, lsrs)| (*venue, lsrs))).collect()
+	}
+
+	/// Latest long share of each pair, averaged across venues weighted by each one's open interest in it. Sorted ascending.
+	pub fn aggregated(&self) -> Vec<(Pair, f64)> {
+		let by_venue = self.by_venue();
+		let Some((_, primary)) = by_venue.first() else {
+			return Vec::new();
+		};
+
+		let mut aggregated = primary
+			.iter()
+			.filter_map(|lsrs| {
+				let (weighted_sum, total_oi) = by_venue
+					.iter()
+					.filter_map(|(venue, venue_lsrs)| {
+						let long = venue_lsrs.iter().find(|l| l.pair == lsrs.pair)?.last()?.long();
+						let oi = self.open_interest.iter().find(|(v, _)| v == venue)?.1.get(&lsrs.pair.to_string())?;
+						Some((long * oi, *oi))
+					})
+					.fold((0.0, 0.0), |(sum, total), (weighted, oi)| (sum + weighted, total + oi));
+				(total_oi > 0.0).then(|| (lsrs.pair, weighted_sum / total_oi))
+			})
+			.collect::<Vec<_>>();
+		aggregated.sort_by(|a, b| a.1.total_cmp(&b.1));
+		aggregated
+	}
+}
+
+/// Collects fresh data, adding it to the per-pair history
+pub async fn get(tf: Timeframe, range: RequestRange, limiters: &Limiters) -> Result<LsrData> {
+	let mut data = collect(tf, range, limiters).await?;
+	// a file per pair, so keep the runtime free while going through them
+	let sources = data.sources.clone();
+	match tokio::task::spawn_blocking(move || store_history(&sources)).await {
+		Ok(histories) => {
+			let now = Utc::now();
+			data.z_scores = z_scores(&histories, now);
+			data.z_score_span_days = histories
+				.values()
+				.flat_map(|history| LsrSource::ALL.into_iter().filter(|source| history.z_score(*source, now).is_some()).filter_map(|source| history.span(source, now)))
+				.max()
+				.map(|span| span.num_days());
+		}
+		Err(e) => warn!("Failed to store LSR history: {:?}", e),
+	}
+	Ok(data)
+}
+
+//Q: potentially fix to "1D", so a single request covers the full month of history
+pub async fn collect(tf: Timeframe, range: RequestRange, limiters: &Limiters) -> Result<LsrData> {
+	let limiter = &limiters.binance_futures;
+	let data_limiter = &limiters.binance_futures_data;
 	let mut bn = binance::Binance::default();
 	bn.set_max_tries(3);
 
 	let m = "Binance/Futures".into();
-	let pairs = bn.exchange_info(m).await.unwrap().usdt_pairs().collect::<Vec<_>>();
+	let pairs = limiter.run(1, bn.exchange_info(m)).await?.usdt_pairs().collect::<Vec<_>>();
 	let pairs_len = pairs.len();
 
-	let lsr_no_data_pairs_file = share_dir!().join("lsr_no_data_pairs");
-	let lsr_no_data_pairs = match std::fs::metadata(&lsr_no_data_pairs_file) {
-		Ok(metadata) => {
-			let age = metadata.modified().unwrap().elapsed().unwrap();
-			// Binance could start supporting any of the ignored pairs, so we refetch once a month
-			if age < std::time::Duration::from_hours(30 * 24) {
-				std::fs::read_to_string(&lsr_no_data_pairs_file)
-					.map(|s| s.lines().filter(|s| !s.is_empty()).map(|s| s.into()).collect())
-					.unwrap_or_else(|_| Vec::new())
-			} else {
-				Vec::new()
-			}
-		}
-		Err(_) => Vec::new(),
-	};
-	let lsr_pairs = pairs.into_iter().filter(|p| !lsr_no_data_pairs.contains(&p.to_string())).collect::<Vec<_>>();
+	let now = Utc::now();
+	let mut no_data_schedule = NoDataSchedule::load();
+	no_data_schedule.retain_listed(&pairs);
+	let lsr_pairs = pairs.into_iter().filter(|p| !no_data_schedule.should_skip(&p.to_string(), now)).collect::<Vec<_>>();
 
 	let bn_arc = Arc::new(bn);
 	let new_no_data_pairs = Arc::new(Mutex::new(Vec::new()));
@@ -41,10 +476,9 @@ pub async fn get(tf: Timeframe, range: RequestRange) -> Result<String> {
 		let bn = Arc::clone(&bn_arc);
 		let new_no_data_pairs = Arc::clone(&new_no_data_pairs);
 		async move {
-			match bn.lsr(*p, tf, range, "Global".into()).await {
+			let global = match data_limiter.run(FUTURES_DATA_WEIGHT, bn.lsr(*p, tf, range, LsrSource::GlobalAccounts.who().into())).await {
 				Ok(lsr_vec) if !lsr_vec.is_empty() => Some(lsr_vec),
 				Ok(_) => {
-					//TODO: write all pairs explicitly without data to XDG_STATE, retry for all once a month
 					info!("No data for {}", p);
 					new_no_data_pairs.lock().unwrap().push(p.to_string());
 					None
@@ -53,21 +487,216 @@ pub async fn get(tf: Timeframe, range: RequestRange) -> Result<String> {
 					warn!("Couldn't fetch data for {}: {:?}", p, e);
 					None
 				}
+			}?;
+
+			// pairs with no global data have none for top traders either, so only ask for those that do
+			let mut by_source = vec![(LsrSource::GlobalAccounts, global)];
+			for source in [LsrSource::TopAccounts, LsrSource::TopPositions] {
+				match data_limiter.run(FUTURES_DATA_WEIGHT, bn.lsr(*p, tf, range, source.who().into())).await {
+					Ok(lsr_vec) if !lsr_vec.is_empty() => by_source.push((source, lsr_vec)),
+					Ok(_) => info!("No {} data for {}", source.title(), p),
+					Err(e) => warn!("Couldn't fetch {} data for {}: {:?}", source.title(), p, e),
+				}
 			}
+			let oi = latest_open_interest(&*bn, *p, tf, data_limiter).await;
+			Some((by_source, oi))
 		}
 	});
-	let results = join_all(handles).await;
+	let (results, bybit) = futures::join!(join_all(handles), collect_bybit(&lsr_pairs, tf, range, &limiters.bybit));
 	let new_no_data_pairs = Arc::try_unwrap(new_no_data_pairs).expect("All locks have been awaited").into_inner().unwrap();
-	if !new_no_data_pairs.is_empty() {
-		let all_no_data_pairs = lsr_no_data_pairs.into_iter().chain(new_no_data_pairs).collect::<Vec<_>>();
-		std::fs::write(&lsr_no_data_pairs_file, all_no_data_pairs.join("\n")).unwrap();
+	for pair in &new_no_data_pairs {
+		no_data_schedule.record_empty(pair, now);
+	}
+
+	let mut by_source: HashMap<LsrSource, Vec<Lsrs>> = HashMap::new();
+	let mut binance_oi = BTreeMap::new();
+	for (pair_by_source, oi) in results.into_iter().flatten() {
+		if let Some((_, global)) = pair_by_source.first() {
+			no_data_schedule.record_data(&global.pair.to_string());
+			if let Some(oi) = oi {
+				binance_oi.insert(global.pair.to_string(), oi);
+			}
+		}
+		for (source, lsrs) in pair_by_source {
+			by_source.entry(source).or_default().push(lsrs);
+		}
+	}
+	if let Err(e) = no_data_schedule.save() {
+		warn!("Failed to save LSR no-data schedule: {:?}", e);
+	}
+
+	let mut open_interest = vec![(Venue::Binance, binance_oi)];
+	let mut other_venues = Vec::new();
+	match bybit {
+		Ok((lsrs, oi)) => {
+			other_venues.push((Venue::Bybit, lsrs));
+			open_interest.push((Venue::Bybit, oi));
+		}
+		Err(e) => warn!("Couldn't collect Bybit LSR data: {:?}", e),
+	}
+	let sources = LsrSource::ALL
+		.into_iter()
+		.filter_map(|source| by_source.remove(&source).map(|lsrs| (source, SortedLsrs::build(lsrs))))
+		.collect();
+
+	Ok(LsrData {
+		market: m.to_string(),
+		pairs_len,
+		sources,
+		other_venues,
+		open_interest,
+		z_scores: Vec::new(),
+		z_score_span_days: None,
+		candles: BTreeMap::new(),
+	})
+}
+
+/// Pairs Binance returned no LSR data for, and when each is to be asked again. Lives in XDG state.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct NoDataSchedule {
+	pairs: BTreeMap<String, NoDataEntry>,
+}
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct NoDataEntry {
+	last_empty: DateTime<Utc>,
+	retry_at: DateTime<Utc>,
+	/// Consecutive refreshes it came back empty on
+	strikes: u32,
+}
+impl NoDataSchedule {
+	fn path() -> Result<std::path::PathBuf> {
+		let dirs = xdg::BaseDirectories::with_prefix(env!("CARGO_PKG_NAME"))?;
+		Ok(dirs.place_state_file("lsr_no_data_pairs.json")?)
+	}
+
+	fn load() -> Self {
+		let mut schedule: Self = Self::path()
+			.ok()
+			.and_then(|path| std::fs::read_to_string(path).ok())
+			.and_then(|json| serde_json::from_str(&json).ok())
+			.unwrap_or_default();
+		schedule.import_legacy();
+		schedule
+	}
+
+	/// Takes over the pairs of [LEGACY_NO_DATA_FILE], due a month after it was written as they used to be, and removes it
+	fn import_legacy(&mut self) {
+		let legacy = share_dir!().join(LEGACY_NO_DATA_FILE);
+		let Ok(list) = std::fs::read_to_string(&legacy) else {
+			return;
+		};
+		let written = std::fs::metadata(&legacy).and_then(|m| m.modified()).map(DateTime::<Utc>::from).unwrap_or_else(|_| Utc::now());
+		for pair in list.lines().filter(|s| !s.is_empty()) {
+			self.pairs.entry(pair.to_owned()).or_insert(NoDataEntry {
+				last_empty: written,
+				retry_at: written + chrono::Duration::days(NO_DATA_MAX_RETRY_DAYS),
+				strikes: 1,
+			});
+		}
+		if let Err(e) = std::fs::remove_file(&legacy) {
+			warn!("Failed to remove {}: {e}", legacy.display());
+		}
+	}
+
+	fn save(&self) -> Result<()> {
+		std::fs::write(Self::path()?, serde_json::to_string_pretty(self)?)?;
+		Ok(())
+	}
+
+	fn should_skip(&self, pair: &str, now: DateTime<Utc>) -> bool {
+		self.pairs.get(pair).is_some_and(|entry| entry.retry_at > now)
+	}
+
+	fn record_empty(&mut self, pair: &str, now: DateTime<Utc>) {
+		let strikes = self.pairs.get(pair).map_or(0, |entry| entry.strikes) + 1;
+		let backoff_days = (NO_DATA_BASE_RETRY_DAYS << (strikes - 1).min(16)).min(NO_DATA_MAX_RETRY_DAYS);
+		self.pairs.insert(pair.to_owned(), NoDataEntry {
+			last_empty: now,
+			retry_at: now + chrono::Duration::days(backoff_days),
+			strikes,
+		});
 	}
 
-	let lsrs: Vec<Lsrs> = results.into_iter().flatten().collect();
-	let sorted_lsrs = SortedLsrs::build(lsrs);
+	fn record_data(&mut self, pair: &str) {
+		self.pairs.remove(pair);
+	}
 
-	let mut s = String::new();
-	let display_rows_ceiling = std::cmp::min(SLICE_SIZE, sorted_lsrs.len() / 2 /*floor*/);
+	/// Forgets pairs no longer listed
+	fn retain_listed(&mut self, listed: &[Pair]) {
+		let listed = listed.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+		self.pairs.retain(|pair, _| listed.contains(pair));
+	}
+}
+
+/// Global accounts ratios and latest open interest of those of `pairs` Bybit lists
+async fn collect_bybit(pairs: &[Pair], tf: Timeframe, range: RequestRange, limiter: &RequestLimiter) -> Result<(SortedLsrs, BTreeMap<String, f64>)> {
+	let mut by = bybit::Bybit::default();
+	by.set_max_tries(3);
+
+	let listed = limiter.run(1, by.exchange_info("Bybit/Linear".into())).await?.usdt_pairs().collect::<Vec<_>>();
+	let by_arc = Arc::new(by);
+	let handles = pairs.iter().filter(|p| listed.contains(p)).map(|p| {
+		let by = Arc::clone(&by_arc);
+		async move {
+			let lsrs = match limiter.run(FUTURES_DATA_WEIGHT, by.lsr(*p, tf, range, LsrSource::GlobalAccounts.who().into())).await {
+				Ok(lsr_vec) if !lsr_vec.is_empty() => lsr_vec,
+				Ok(_) => return None,
+				Err(e) => {
+					warn!("Couldn't fetch Bybit data for {}: {:?}", p, e);
+					return None;
+				}
+			};
+			let oi = latest_open_interest(&*by, *p, tf, limiter).await;
+			Some((lsrs, oi))
+		}
+	});
+
+	let mut lsrs = Vec::new();
+	let mut open_interest = BTreeMap::new();
+	for (pair_lsrs, oi) in join_all(handles).await.into_iter().flatten() {
+		if let Some(oi) = oi {
+			open_interest.insert(pair_lsrs.pair.to_string(), oi);
+		}
+		lsrs.push(pair_lsrs);
+	}
+	if lsrs.is_empty() {
+		bail!("None of {} requested pairs returned data", pairs.len());
+	}
+	Ok((SortedLsrs::build(lsrs), open_interest))
+}
+
+/// Most recent open interest of `pair` in base asset, if the exchange has it
+async fn latest_open_interest(exchange: &dyn Exchange, pair: Pair, tf: Timeframe, limiter: &RequestLimiter) -> Option<f64> {
+	match limiter.run(FUTURES_DATA_WEIGHT, exchange.open_interest(pair, tf, 1.into())).await {
+		Ok(oi) => oi.last().map(|oi| oi.val_asset),
+		Err(e) => {
+			warn!("Couldn't fetch open interest for {}: {:?}", pair, e);
+			None
+		}
+	}
+}
+
+pub fn render(data: &LsrData, ranking: LsrRanking) -> Result<String> {
+	let global = data.get(LsrSource::GlobalAccounts).ok_or_else(|| eyre!("No global LSR data collected"))?;
+
+	let ranked_sources = data.ranked_sources(ranking);
+	let columns = ranked_sources
+		.iter()
+		.map(|(source, lsrs)| (source.title().to_owned(), lsrs))
+		.chain(data.other_venues.iter().map(|(venue, lsrs)| (format!("{venue} {}", LsrSource::GlobalAccounts.title()), lsrs)))
+		.collect::<Vec<_>>();
+
+	let mut s = match ranking {
+		LsrRanking::Level => String::new(),
+		LsrRanking::ZScore => match data.z_score_span_days {
+			Some(days) => format!("Binance ranked by z-score of long share within each pair's own history, covering up to {days}d of the {HISTORY_RETENTION_DAYS}d kept\n"),
+			None => format!("Binance ranked by z-score of long share, but no pair has {Z_SCORE_MIN_SPAN_DAYS}d of history yet\n"),
+		},
+	};
+	// each column runs as deep as its own pairs allow, those without a single row are left out (their averages below still say so)
+	let rows_of = |lsrs: &SortedLsrs| std::cmp::min(SLICE_SIZE, lsrs.len() / 2 /*floor*/);
+	let table_columns = columns.iter().filter(|(_, lsrs)| rows_of(lsrs) > 0).collect::<Vec<_>>();
+	let display_rows_ceiling = table_columns.iter().map(|(_, lsrs)| rows_of(lsrs)).max().unwrap_or(0);
 	let width = Lsrs::CHANGE_STR_LEN;
 	for i in 0..display_rows_ceiling {
 		if i == 0 {
@@ -76,30 +705,354 @@ pub async fn get(tf: Timeframe, range: RequestRange) -> Result<String> {
 				unsafe { std::mem::transmute::<std::fmt::Arguments, std::fmt::Arguments>(format_args!("{:<width$}", format!("Most {t} (% longs)"), width = width)) }
 			};
 
-			s.write_fmt(format_args!("{}{}", title("Shorted"), title("Longed"))).unwrap(); // match formatting of `fmt_lsr` (when counting, don't forget all symbols outside of main paddings)
-		} else {
+			let source_titles = table_columns.iter().map(|(t, _)| format!("{:<w$}", t, w = 2 * width)).collect::<Vec<_>>();
+			s.push_str(source_titles.join(COLUMN_GAP).trim_end());
 			s.push('\n');
+			let column_titles = table_columns.iter().map(|_| format!("{}{}", title("Shorted"), title("Longed"))).collect::<Vec<_>>(); // match formatting of `fmt_lsr` (when counting, don't forget all symbols outside of main paddings)
+			s.push_str(&column_titles.join(COLUMN_GAP));
 		}
-		s.push_str(&sorted_lsrs.display_most_shorted_longed_row(i)?);
+		s.push('\n');
+		let rows = table_columns
+			.iter()
+			.map(|(_, lsrs)| match i < rows_of(lsrs) {
+				true => lsrs.display_most_shorted_longed_row(i),
+				false => Ok(" ".repeat(2 * width)),
+			})
+			.collect::<Result<Vec<_>>>()?;
+		s.push_str(&rows.join(COLUMN_GAP));
 	}
 	s.push_str(&format!("\n{:-^width$}", "", width = width));
-	s.push_str(&format!(
-		"\nAverage: {:.2}",
-		sorted_lsrs.iter().map(|lsr| lsr.last().unwrap().long()).sum::<f64>() / sorted_lsrs.len() as f64
-	));
-	s.push_str(&format!("\nCollected for {}/{pairs_len} pairs on {}", sorted_lsrs.len(), m));
+	for (title, lsrs) in &columns {
+		match lsrs.average() {
+			Some(average) => s.push_str(&format!("\nAverage ({title}): {average:.2}")),
+			None => s.push_str(&format!("\nAverage ({title}): no pairs with valid data")),
+		}
+	}
+
+	for weighting in [LsrWeighting::QuoteVolume, LsrWeighting::OpenInterest] {
+		if let Some(average) = data.weighted_average(weighting) {
+			s.push_str(&format!("\nAverage ({}, {}): {average:.2}", LsrSource::GlobalAccounts.title(), weighting.title()));
+		}
+	}
+
+	if let Some(top) = data.get(LsrSource::TopPositions) {
+		let disagreements = disagreements(global, top);
+		if !disagreements.is_empty() {
+			s.push_str("\n\nTop traders vs crowd, biggest disagreements (long share):");
+			for (pair, crowd, top) in disagreements.into_iter().take(SLICE_SIZE) {
+				s.push_str(&format!("\n{:<14}crowd {crowd:.2} | top {top:.2} ({:+.2})", pair.to_string(), top - crowd));
+			}
+		}
+	}
+
+	let by_change = data.sources.iter().map(|(source, lsrs)| (source, lsrs.by_change())).collect::<Vec<_>>();
+	let change_rows = by_change.iter().map(|(_, lsrs)| std::cmp::min(SLICE_SIZE, lsrs.len() / 2)).min().unwrap_or(0);
+	if change_rows > 0 {
+		s.push_str("\n\nBiggest moves in long share over the range:\n");
+		let cell_width = 2 * width;
+		let source_titles = by_change.iter().map(|(source, _)| format!("{:<w$}", source.title(), w = 2 * cell_width)).collect::<Vec<_>>();
+		s.push_str(source_titles.join(COLUMN_GAP).trim_end());
+		s.push('\n');
+		let column_titles = by_change.iter().map(|_| format!("{:<w$}{:<w$}", "Fell", "Rose", w = cell_width)).collect::<Vec<_>>();
+		s.push_str(column_titles.join(COLUMN_GAP).trim_end());
+		for i in 0..change_rows {
+			let row = by_change
+				.iter()
+				.map(|(_, lsrs)| {
+					let cell = |lsrs: &Lsrs| format!("{:<w$}", format!("{:<14}{:+.2}", lsrs.pair.to_string(), change(lsrs)), w = cell_width);
+					format!("{}{}", cell(&lsrs[i]), cell(&lsrs[lsrs.len() - i - 1]))
+				})
+				.collect::<Vec<_>>();
+			s.push('\n');
+			s.push_str(row.join(COLUMN_GAP).trim_end());
+		}
+	}
+
+	let divergences = data.divergences();
+	if !divergences.is_empty() {
+		s.push_str("\n\nPrice vs long share, strongest divergences:");
+		for d in divergences.iter().take(SLICE_SIZE) {
+			s.push_str(&format!(
+				"\n{:<14}{:<17}price {:+.2}% | longs {:+.2} (strength {:.2})",
+				d.pair,
+				d.kind.to_string(),
+				d.price_change * 100.0,
+				d.long_change,
+				d.strength
+			));
+		}
+	}
+
+	let aggregated = data.aggregated();
+	if !data.other_venues.is_empty() && !aggregated.is_empty() {
+		let venues = data.by_venue().iter().map(|(venue, _)| venue.to_string()).collect::<Vec<_>>().join(", ");
+		s.push_str(&format!("\n\nAggregated across {venues}, weighted by open interest (long share):"));
+		let n = std::cmp::min(SLICE_SIZE, aggregated.len() / 2);
+		for ((shorted, shorted_long), (longed, longed_long)) in aggregated.iter().zip(aggregated.iter().rev()).take(n) {
+			s.push_str(&format!("\n{:<14}{shorted_long:.2}    {:<14}{longed_long:.2}", shorted.to_string(), longed.to_string()));
+		}
+	}
+
+	s.push_str(&format!("\n\nCollected for {}/{} pairs on {}", global.len(), data.pairs_len, data.market));
+	if data.candles.is_empty() {
+		s.push_str(&format!("\nNo candles for {} yet, so no quote volume weighting, price changes or divergences", data.market));
+	}
+
+	let excluded = columns.iter().filter(|(_, lsrs)| !lsrs.excluded.is_empty()).collect::<Vec<_>>();
+	if !excluded.is_empty() {
+		s.push_str("\n\nExcluded from ranking:");
+		for (title, lsrs) in excluded {
+			s.push_str(&format!("\n{title}: {} pairs", lsrs.excluded.len()));
+			for (pair, reason) in &lsrs.excluded {
+				s.push_str(&format!("\n  {pair:<14}{reason}"));
+			}
+		}
+	}
 	Ok(s)
 }
 
-/// Inner values are guaranteed to be sorted
-#[derive(Clone, Debug, derive_more::Deref, derive_more::DerefMut, Deserialize, Serialize)]
+/// Market-wide long share of global accounts over the fetched range, under every weighting
+pub fn plot_market_wide(data: &LsrData) -> Plot {
+	let mut plot = Plot::new();
+	plot.set_layout(plotly::Layout::new().title(format!("Market-wide long share, {}", data.market)));
+
+	for weighting in LsrWeighting::ALL {
+		let (x_values, y_values): (Vec<String>, Vec<f64>) = data.weighted_series(weighting).into_iter().map(|(time, long)| (time.to_rfc3339(), long)).unzip();
+		plot.add_trace(Scatter::new(x_values, y_values).mode(Mode::Lines).name(weighting.title()));
+	}
+	plot
+}
+
+/// Pairs present in both, as (pair, crowd long share, top traders long share), furthest apart first
+pub fn disagreements(crowd: &SortedLsrs, top: &SortedLsrs) -> Vec<(Pair, f64, f64)> {
+	let mut v = crowd
+		.iter()
+		.filter_map(|c| {
+			let t = top.iter().find(|t| t.pair == c.pair)?;
+			Some((c.pair, c.last()?.long(), t.last()?.long()))
+		})
+		.collect::<Vec<_>>();
+	v.sort_by(|a, b| (b.2 - b.1).abs().total_cmp(&(a.2 - a.1).abs()));
+	v
+}
+
+/// Long share over time of one pair, by source, as accumulated over refreshes
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PairLsrHistory {
+	/// (time, long share), ascending by time
+	pub series: HashMap<LsrSource, Vec<(DateTime<Utc>, f64)>>,
+}
+impl PairLsrHistory {
+	fn path(pair: Pair) -> Result<std::path::PathBuf> {
+		let name = pair.to_string();
+		// file names come from parsed pairs, but never let one step outside the history dir
+		if !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+			bail!("Refusing to store LSR history of {name}, not a plain symbol");
+		}
+		let dir = share_dir!().join("lsr_history");
+		std::fs::create_dir_all(&dir)?;
+		Ok(dir.join(format!("{name}.json")))
+	}
+
+	/// Empty if nothing was stored for `pair` yet. An unparsable file is moved aside rather than overwritten by the next save
+	pub fn load(pair: Pair) -> Result<Self> {
+		let path = Self::path(pair)?;
+		let json = match std::fs::read_to_string(&path) {
+			Ok(json) => json,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+			Err(e) => bail!("Failed to read LSR history at {}: {e}", path.display()),
+		};
+		match serde_json::from_str(&json) {
+			Ok(history) => Ok(history),
+			Err(e) => {
+				let aside = path.with_extension(format!("json.unreadable-{}", Utc::now().format("%Y%m%dT%H%M%S")));
+				std::fs::rename(&path, &aside).map_err(|rename_e| eyre!("Unreadable LSR history at {} ({e}) couldn't be moved aside: {rename_e}", path.display()))?;
+				warn!("Moved unreadable LSR history to {}: {e}", aside.display());
+				Ok(Self::default())
+			}
+		}
+	}
+
+	pub fn save(&self, pair: Pair) -> Result<()> {
+		std::fs::write(Self::path(pair)?, serde_json::to_string(self)?)?;
+		Ok(())
+	}
+
+	/// How far back the stored series of `source` goes as of `now`
+	pub fn span(&self, source: LsrSource, now: DateTime<Utc>) -> Option<chrono::Duration> {
+		self.series.get(&source)?.first().map(|(time, _)| now - *time)
+	}
+
+	/// How many standard deviations the latest long share is away from the mean of the stored series
+	pub fn z_score(&self, source: LsrSource, now: DateTime<Utc>) -> Option<f64> {
+		let series = self.series.get(&source)?;
+		if series.len() < Z_SCORE_MIN_SAMPLES || self.span(source, now)? < chrono::Duration::days(Z_SCORE_MIN_SPAN_DAYS) {
+			return None;
+		}
+		let n = series.len() as f64;
+		let mean = series.iter().map(|(_, long)| long).sum::<f64>() / n;
+		let std = (series.iter().map(|(_, long)| (long - mean).powi(2)).sum::<f64>() / n).sqrt();
+		let (_, latest) = series.last()?;
+		(std > 0.0).then(|| (latest - mean) / std)
+	}
+
+	/// Merges in `lsrs`, newer values winning on the same timestamp, and drops what's past retention
+	pub fn extend(&mut self, source: LsrSource, lsrs: &Lsrs) {
+		let series = self.series.entry(source).or_default();
+		let mut by_time = series.drain(..).collect::<BTreeMap<_, _>>();
+		by_time.extend(lsrs.iter().map(|lsr| (lsr.time, lsr.long())));
+
+		let cutoff = Utc::now() - chrono::Duration::days(HISTORY_RETENTION_DAYS);
+		series.extend(by_time.into_iter().filter(|(time, _)| *time >= cutoff));
+	}
+}
+
+/// Merges every fetched Binance series into its pair's stored history, returning the updated histories by pair
+/// Pairs whose history couldn't be read or written are logged and left out
+pub fn store_history(sources: &[(LsrSource, SortedLsrs)]) -> HashMap<String, PairLsrHistory> {
+	let mut by_pair: HashMap<Pair, Vec<(LsrSource, &Lsrs)>> = HashMap::new();
+	for (source, sorted) in sources {
+		for lsrs in sorted.iter() {
+			by_pair.entry(lsrs.pair).or_default().push((*source, lsrs));
+		}
+	}
+
+	let mut histories = HashMap::new();
+	for (pair, series) in by_pair {
+		let mut history = match PairLsrHistory::load(pair) {
+			Ok(history) => history,
+			Err(e) => {
+				warn!("Skipping LSR history of {pair}: {e:?}");
+				continue;
+			}
+		};
+		for (source, lsrs) in series {
+			history.extend(source, lsrs);
+		}
+		if let Err(e) = history.save(pair) {
+			warn!("Failed to save LSR history of {pair}: {e:?}");
+		}
+		histories.insert(pair.to_string(), history);
+	}
+	histories
+}
+
+fn z_scores(histories: &HashMap<String, PairLsrHistory>, now: DateTime<Utc>) -> Vec<(LsrSource, BTreeMap<String, f64>)> {
+	LsrSource::ALL
+		.into_iter()
+		.map(|source| {
+			let by_pair = histories.iter().filter_map(|(pair, history)| history.z_score(source, now).map(|z| (pair.clone(), z))).collect();
+			(source, by_pair)
+		})
+		.collect()
+}
+
+/// Stored long share of `pair` from every source, against its price over the same period
+pub async fn plot_pair(pair: &str, limiters: &Limiters) -> Result<Plot> {
+	let pair = pair.to_uppercase();
+	let parsed_pair: Pair = pair.as_str().try_into().map_err(|e| eyre!("Invalid pair {pair}: {e:?}"))?;
+	let history = PairLsrHistory::load(parsed_pair)?;
+	if history.series.is_empty() {
+		bail!("No LSR history stored for {pair}");
+	}
+	let closes = crate::market_structure::closes(parsed_pair, "1h".into(), (HISTORY_RETENTION_DAYS as u32 * 24).into(), "Binance/Futures".into(), limiters)
+		.await
+		.unwrap_or_else(|e| {
+			warn!("Failed to fetch closes of {pair}: {:?}", e);
+			Vec::new()
+		});
+
+	let mut plot = Plot::new();
+	let layout = plotly::Layout::new()
+		.title(format!("{pair} long share vs price"))
+		.y_axis(Axis::new().title("Long share"))
+		.y_axis2(Axis::new().title("Price").overlaying("y").side(AxisSide::Right));
+	plot.set_layout(layout);
+
+	for source in LsrSource::ALL {
+		let Some(series) = history.series.get(&source) else { continue };
+		let (x_values, y_values): (Vec<String>, Vec<f64>) = series.iter().map(|(time, long)| (time.to_rfc3339(), *long)).unzip();
+		plot.add_trace(Scatter::new(x_values, y_values).mode(Mode::Lines).name(source.title()));
+	}
+	let (x_values, y_values): (Vec<String>, Vec<f64>) = closes.into_iter().map(|(time, close)| (time.to_rfc3339(), close)).unzip();
+	plot.add_trace(Scatter::new(x_values, y_values).mode(Mode::Lines).name("Price").y_axis("y2"));
+
+	Ok(plot)
+}
+
+/// Of long share, from the first to the last value of the range. Only called on series [SortedLsrs] admitted.
+fn change(lsrs: &Lsrs) -> f64 {
+	lsrs.last().unwrap().long() - lsrs.first().unwrap().long()
+}
+
+/// Why a pair's series was left out of ranking
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, derive_more::Display)]
+pub enum ExclusionReason {
+	#[display("empty series")]
+	Empty,
+	#[display("non-finite long share at {_0}")]
+	NonFinite(DateTime<Utc>),
+}
+
+impl ExclusionReason {
+	/// Why a series of (time, long share) can't be ranked, `None` if it can
+	fn of(longs: &[(DateTime<Utc>, f64)]) -> Option<Self> {
+		if longs.is_empty() {
+			return Some(Self::Empty);
+		}
+		longs.iter().find(|(_, long)| !long.is_finite()).map(|(time, _)| Self::NonFinite(*time))
+	}
+}
+
+/// Inner values are guaranteed to be non-empty, finite and sorted, by latest long share unless built by a different key
+#[derive(Clone, Debug, Default, derive_more::Deref, derive_more::DerefMut, Deserialize, Serialize)]
 pub struct SortedLsrs {
+	#[deref]
+	#[deref_mut]
 	v: Vec<Lsrs>,
+	/// Pairs `build` refused, with why
+	#[serde(default)]
+	pub excluded: Vec<(String, ExclusionReason)>,
 }
 impl SortedLsrs {
-	pub fn build(mut v: Vec<Lsrs>) -> Self {
-		v.sort_by(|a, b| a.last().unwrap().long().partial_cmp(&b.last().unwrap().long()).unwrap());
-		Self { v }
+	pub fn build(v: Vec<Lsrs>) -> Self {
+		let mut excluded = Vec::new();
+		let mut v = v
+			.into_iter()
+			.filter(|lsrs| {
+				let longs = lsrs.iter().map(|lsr| (lsr.time, lsr.long())).collect::<Vec<_>>();
+				let Some(reason) = ExclusionReason::of(&longs) else {
+					return true;
+				};
+				warn!("Excluding {} from LSR ranking: {reason}", lsrs.pair);
+				excluded.push((lsrs.pair.to_string(), reason));
+				false
+			})
+			.collect::<Vec<_>>();
+		v.sort_by(|a, b| a.last().unwrap().long().total_cmp(&b.last().unwrap().long()));
+		Self { v, excluded }
+	}
+
+	/// Reordered by `z_score` of each pair, dropping those it has none for
+	pub fn by_z_score(&self, z_score: impl Fn(&str) -> Option<f64>) -> Self {
+		let mut scored = self.v.iter().filter_map(|lsrs| z_score(&lsrs.pair.to_string()).map(|z| (z, lsrs.clone()))).collect::<Vec<_>>();
+		scored.sort_by(|a, b| a.0.total_cmp(&b.0));
+		Self {
+			v: scored.into_iter().map(|(_, lsrs)| lsrs).collect(),
+			excluded: self.excluded.clone(),
+		}
+	}
+
+	/// Reordered by change of long share over the range, the biggest fall first
+	pub fn by_change(&self) -> Self {
+		let mut v = self.v.clone();
+		v.sort_by(|a, b| change(a).total_cmp(&change(b)));
+		Self { v, excluded: self.excluded.clone() }
+	}
+
+	/// Mean of latest long share, `None` if nothing was admitted
+	pub fn average(&self) -> Option<f64> {
+		(!self.is_empty()).then(|| self.iter().map(|lsrs| lsrs.last().unwrap().long()).sum::<f64>() / self.len() as f64)
 	}
 
 	pub fn display_most_shorted_longed_row(&self, i: usize) -> Result<String> {
@@ -114,3 +1067,78 @@ impl SortedLsrs {
 impl Mock for SortedLsrs {
 	const NAME: &'static str = "lsrs";
 }
+impl Mock for LsrData {
+	const NAME: &'static str = "lsr_data";
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn at(minutes: i64) -> DateTime<Utc> {
+		DateTime::UNIX_EPOCH + chrono::Duration::minutes(minutes)
+	}
+
+	#[test]
+	fn empty_series_excluded() {
+		assert_eq!(ExclusionReason::of(&[]), Some(ExclusionReason::Empty));
+	}
+
+	#[test]
+	fn non_finite_series_excluded_at_first_bad_value() {
+		let longs = [(at(0), 0.5), (at(5), f64::NAN), (at(10), f64::INFINITY)];
+		assert_eq!(ExclusionReason::of(&longs), Some(ExclusionReason::NonFinite(at(5))));
+	}
+
+	#[test]
+	fn finite_series_admitted() {
+		assert_eq!(ExclusionReason::of(&[(at(0), 0.5), (at(5), 0.6)]), None);
+	}
+
+	#[test]
+	fn no_data_backoff_doubles() {
+		let mut schedule = NoDataSchedule::default();
+		for expected_days in [1, 2, 4, 8] {
+			schedule.record_empty("FOOUSDT", at(0));
+			assert_eq!(schedule.pairs["FOOUSDT"].retry_at, at(0) + chrono::Duration::days(expected_days));
+		}
+		assert!(schedule.should_skip("FOOUSDT", at(0)));
+		assert!(!schedule.should_skip("FOOUSDT", at(0) + chrono::Duration::days(8)));
+	}
+
+	#[test]
+	fn no_data_backoff_capped() {
+		let mut schedule = NoDataSchedule::default();
+		for _ in 0..40 {
+			schedule.record_empty("FOOUSDT", at(0));
+		}
+		assert_eq!(schedule.pairs["FOOUSDT"].retry_at, at(0) + chrono::Duration::days(NO_DATA_MAX_RETRY_DAYS));
+	}
+
+	#[test]
+	fn no_data_entry_removed_on_data() {
+		let mut schedule = NoDataSchedule::default();
+		schedule.record_empty("FOOUSDT", at(0));
+		schedule.record_data("FOOUSDT");
+		assert!(schedule.pairs.is_empty());
+		assert!(!schedule.should_skip("FOOUSDT", at(0)));
+	}
+
+	#[test]
+	fn no_data_entry_removed_on_delisting() {
+		let mut schedule = NoDataSchedule::default();
+		schedule.record_empty("BTCUSDT", at(0));
+		schedule.record_empty("FOOUSDT", at(0));
+		let listed: Vec<Pair> = vec!["BTCUSDT".try_into().unwrap()];
+		schedule.retain_listed(&listed);
+		assert_eq!(schedule.pairs.keys().collect::<Vec<_>>(), ["BTCUSDT"]);
+	}
+
+	#[test]
+	fn build_reports_nothing_for_no_input() {
+		let sorted = SortedLsrs::build(Vec::new());
+		assert!(sorted.is_empty());
+		assert!(sorted.excluded.is_empty());
+		assert_eq!(sorted.average(), None);
+	}
+}
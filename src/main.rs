@@ -5,10 +5,10 @@ use std::{
 };
 
 use axum::{
-    Router,
-    extract::State,
-    http::StatusCode,
-    response::Html,
+    Json, Router,
+    extract::{Path, Query, State},
+    http::{StatusCode, header},
+    response::{Html, IntoResponse},
     routing::{get, post},
 };
 use clap::{Args, Parser, Subcommand};
@@ -18,6 +18,7 @@ use v_exchanges::RequestRange;
 use v_utils::prelude::*;
 
 mod cme;
+mod limiter;
 mod lsr;
 mod market_structure;
 
@@ -43,6 +44,30 @@ impl Default for Commands {
 struct ServeArgs {
     #[arg(long)]
     mock: bool,
+    /// Lookbacks (in weekly reports) to compute the COT Index over
+    #[arg(long, value_delimiter = ',', default_value = "26,52,156")]
+    cot_lookbacks: Vec<usize>,
+    /// Most requests allowed in flight at once, per exchange
+    #[arg(long, default_value_t = 16)]
+    max_in_flight: usize,
+    /// Binance futures request weight to spend per minute at most (Binance allows 2400)
+    #[arg(long, default_value_t = 1200)]
+    binance_futures_weight_per_minute: u32,
+    /// Binance futures long/short ratio and open interest history requests to make per 5 minutes at most (Binance allows 1000)
+    #[arg(long, default_value_t = 1000)]
+    binance_futures_data_requests_per_5m: u32,
+    /// Binance spot request weight to spend per minute at most (Binance allows 6000)
+    #[arg(long, default_value_t = 3000)]
+    binance_spot_weight_per_minute: u32,
+    /// Bybit requests to make per minute at most (Bybit allows 600 per 5 seconds)
+    #[arg(long, default_value_t = 3000)]
+    bybit_requests_per_minute: u32,
+    /// Markets to build market-structure plots for, the first shown by default
+    #[arg(long, value_delimiter = ',', default_value = "Binance/Futures,Binance/Spot,Bybit/Linear")]
+    markets: Vec<String>,
+    /// Most consecutive missing candles of a pair to forward-fill in market-structure plots, longer gaps drop the pair
+    #[arg(long, default_value_t = 3)]
+    max_fill_gap: usize,
 }
 
 #[tokio::main]
@@ -57,9 +82,25 @@ async fn main() {
 
 async fn serve(args: ServeArgs) -> Result<()> {
     let dashboards = Arc::new(RwLock::new(DashboardsState {
-        plot_html: "Waiting for MarketStructure data...".into(),
+        // keyed by how the parsed market displays, which is what `update_plot` reports under
+        market_plots: args
+            .markets
+            .iter()
+            .map(|m| (v_exchanges::AbsMarket::from(m.as_str()).to_string(), "Waiting for MarketStructure data...".into()))
+            .collect(),
         lsr_str: "Waiting for LSR data...".into(),
+        lsr_data: None,
+        market_candles: HashMap::new(),
+        legacy_plot_html: None,
+        lsr_plot_html: String::new(),
         cme_str: "Waiting for CME data...".into(),
+        cme_plot_html: String::new(),
+        cme_table_html: String::new(),
+        cme_table_md: String::new(),
+        cme_table_csv: String::new(),
+        cme_price_plot_html: String::new(),
+        cme_assets_html: "Waiting for CME data...".into(),
+        cftc_history: cme::CftcHistory::default(),
     }));
     let routes = vec![
         RouteInfo {
@@ -70,37 +111,108 @@ async fn serve(args: ServeArgs) -> Result<()> {
         RouteInfo {
             path: "/dashboards".to_string(),
             description: "Dashboards - Main dashboard view".to_string(),
+            children: vec![
+                RouteInfo {
+                    path: "/dashboards/cme/net_positions.html".to_string(),
+                    description: "CFTC net positioning chart export".to_string(),
+                    children: vec![],
+                },
+                RouteInfo {
+                    path: "/dashboards/cme/report.md".to_string(),
+                    description: "Latest CFTC reports as markdown".to_string(),
+                    children: vec![],
+                },
+                RouteInfo {
+                    path: "/dashboards/cme/report.csv".to_string(),
+                    description: "Latest CFTC reports as CSV".to_string(),
+                    children: vec![],
+                },
+            ],
+        },
+        RouteInfo {
+            path: "/lsr".to_string(),
+            description: "Every pair's LSR, sortable and filterable by column".to_string(),
+            children: vec![],
+        },
+        RouteInfo {
+            path: "/api/lsr?pair=BTCUSDT".to_string(),
+            description: "Latest LSR of a pair, its change and rank among all pairs".to_string(),
+            children: vec![],
+        },
+        RouteInfo {
+            path: "/lsr/BTCUSDT".to_string(),
+            description: "LSR history of a pair against its price, any pair in place of BTCUSDT".to_string(),
+            children: vec![],
+        },
+        RouteInfo {
+            path: "/cme/assets".to_string(),
+            description: "CFTC positioning compared across assets".to_string(),
             children: vec![],
         },
+        RouteInfo {
+            path: "/api/cme/history".to_string(),
+            description: "Every stored CFTC report, as JSON".to_string(),
+            children: vec![RouteInfo {
+                path: "/api/cme/latest".to_string(),
+                description: "Latest CFTC report of each kind, as JSON".to_string(),
+                children: vec![],
+            }],
+        },
     ];
+    let limiters = Arc::new(limiter::Limiters::new(
+        args.max_in_flight,
+        args.binance_futures_weight_per_minute,
+        args.binance_futures_data_requests_per_5m,
+        args.binance_spot_weight_per_minute,
+        args.bybit_requests_per_minute,
+    ));
     let state = AppState::new(
         routes,
         Arc::clone(&dashboards),
+        Arc::clone(&limiters),
     );
 
     match args.mock {
         true => {
-            *dashboards.write().unwrap() = DashboardsState::load_mock()?;
+            let mut mock = DashboardsState::load_mock()?;
+            match lsr::LsrData::load_mock() {
+                Ok(data) => mock.set_lsr_data(data),
+                Err(e) => eprintln!("No structured LSR snapshot, showing the pre-rendered one: {}", e),
+            }
+            *dashboards.write().unwrap() = mock;
         }
         false => {
             //TODO: get rid of await breaks, none of this should holt
 
             let tf = "5m".into();
             let range = (24 * 12 + 1).into(); // 24h, given `5m` tf
+            // LSR takes its candles from the market-structure refresh of the market it's collected on
+            let lsr_market: v_exchanges::AbsMarket = "Binance/Futures".into();
+            let mut markets = args.markets.iter().map(|market| v_exchanges::AbsMarket::from(market.as_str())).collect::<Vec<_>>();
+            if !markets.iter().any(|m| m.to_string() == lsr_market.to_string()) {
+                markets.push(lsr_market);
+            }
+            for m in markets {
+                let max_fill_gap = args.max_fill_gap;
+                let state_clone = dashboards.clone();
+                let limiters_clone = Arc::clone(&limiters);
+                tokio::spawn(async move {
+                    update_plot(m, range, tf, max_fill_gap, state_clone, limiters_clone).await;
+                });
+            }
+
             let state_clone = dashboards.clone();
+            let limiters_clone = Arc::clone(&limiters);
             tokio::spawn(async move {
-                update_plot(range, tf, state_clone).await;
+                update_lsr(range, tf, state_clone, limiters_clone).await;
             });
 
-            let lsr_str = lsr::get(tf, range).await;
-            if let Ok(mut state) = dashboards.write() {
-                state.lsr_str = lsr_str.unwrap_or_else(|e| format!("Failed to fetch LSR data: {}", e));
-            }
-
-            let cme_str = cme::fetch_cftc_positions().await;
-            if let Ok(mut state) = dashboards.write() {
-                state.cme_str = cme_str.unwrap_or_else(|e| format!("Failed to fetch CME data: {}", e));
-            }
+            let state_clone = dashboards.clone();
+            let cot_lookbacks = args.cot_lookbacks.clone();
+            let limiters_clone = Arc::clone(&limiters);
+            tokio::spawn(async move {
+                update_cme(cot_lookbacks, state_clone, limiters_clone).await;
+            });
         }
     }
 
@@ -108,6 +220,15 @@ async fn serve(args: ServeArgs) -> Result<()> {
         .route("/", get(list_routes))
         .route("/dashboards", get(dashboards_handler))
         .route("/dashboards/snapshot", post(snapshot_handler))
+        .route("/dashboards/cme/net_positions.html", get(cme_net_positions_handler))
+        .route("/dashboards/cme/report.md", get(cme_report_md_handler))
+        .route("/dashboards/cme/report.csv", get(cme_report_csv_handler))
+        .route("/api/lsr", get(lsr_search_handler))
+        .route("/lsr", get(lsr_table_handler))
+        .route("/lsr/{pair}", get(lsr_pair_handler))
+        .route("/cme/assets", get(cme_assets_handler))
+        .route("/api/cme/history", get(cme_history_handler))
+        .route("/api/cme/latest", get(cme_latest_handler))
         .nest_service("/assets", ServeDir::new("assets"))
         .with_state(state);
 
@@ -169,23 +290,107 @@ async fn list_routes(State(state): State<AppState>) -> Html<String> {
 struct AppState {
     routes: Vec<RouteInfo>,
     dashboards: Arc<RwLock<DashboardsState>>,
+    limiters: Arc<limiter::Limiters>,
 }
 
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct DashboardsState {
-    plot_html: String,
+    /// (market, its market-structure plot), in the order given on the command line
+    #[serde(default)]
+    market_plots: Vec<(String, String)>,
+    /// Single plot of snapshots taken before markets were selectable, migrated into `market_plots` on load
+    #[serde(default, rename = "plot_html", skip_serializing)]
+    legacy_plot_html: Option<String>,
     lsr_str: String,
+    #[serde(skip)]
+    lsr_data: Option<lsr::LsrData>,
+    /// Candles of every pair of each market, from its latest market-structure refresh, by pair
+    #[serde(skip)]
+    market_candles: HashMap<String, std::collections::BTreeMap<String, market_structure::RelevantHistoricalData>>,
+    #[serde(default)]
+    lsr_plot_html: String,
     cme_str: String,
+    #[serde(default)]
+    cme_plot_html: String,
+    #[serde(default)]
+    cme_table_html: String,
+    #[serde(default)]
+    cme_table_md: String,
+    #[serde(default)]
+    cme_table_csv: String,
+    #[serde(default)]
+    cme_price_plot_html: String,
+    #[serde(default)]
+    cme_assets_html: String,
+    #[serde(default)]
+    cftc_history: cme::CftcHistory,
+}
+impl DashboardsState {
+    /// Shows `data`, with candles of its market if they've been fetched
+    fn set_lsr_data(&mut self, mut data: lsr::LsrData) {
+        if let Some(candles) = self.market_candles.get(&data.market) {
+            data.candles = candles.clone();
+        }
+        self.lsr_str = lsr::render(&data, lsr::LsrRanking::default()).unwrap_or_else(|e| format!("Failed to render LSR data: {}", e));
+        self.lsr_plot_html = lsr::plot_market_wide(&data).to_html();
+        self.lsr_data = Some(data);
+    }
 }
 impl Mock for DashboardsState {
     const NAME: &'static str = "dashboards";
+
+    fn load_mock() -> std::io::Result<Self> {
+        let json = fs::read_to_string(share_dir!().join(format!("{}.json", Self::NAME)))?;
+        let mut state: Self = serde_json::from_str(&json)?;
+        if let Some(plot) = state.legacy_plot_html.take() {
+            // the only market plotted back then
+            let market = v_exchanges::AbsMarket::from("Binance/Futures").to_string();
+            if !state.market_plots.iter().any(|(m, _)| *m == market) {
+                state.market_plots.insert(0, (market, plot));
+            }
+        }
+        Ok(state)
+    }
 }
 
-async fn dashboards_handler(State(state): State<AppState>) -> Html<String> {
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct DashboardsQuery {
+    #[serde(default)]
+    lsr_ranking: lsr::LsrRanking,
+    /// Whose market-structure plot to show, the first one if unset
+    market: Option<String>,
+}
+impl DashboardsQuery {
+    fn href(&self) -> String {
+        format!("?{}", serde_urlencoded::to_string(self).unwrap())
+    }
+}
+
+async fn dashboards_handler(State(state): State<AppState>, Query(query): Query<DashboardsQuery>) -> Html<String> {
     let state = state.dashboards.read().unwrap();
-    std::fs::write("./tmp/plot.html", &state.plot_html).unwrap();
-    let html = state.plot_html.clone();
+    let html = match &query.market {
+        Some(market) => state.market_plots.iter().find(|(m, _)| m == market),
+        None => state.market_plots.first(),
+    }
+    .map_or_else(|| "No MarketStructure data for this market".to_string(), |(_, plot)| plot.clone());
+    std::fs::write("./tmp/plot.html", &html).unwrap();
+    let market_links = state
+        .market_plots
+        .iter()
+        .map(|(m, _)| {
+            let href = DashboardsQuery { market: Some(m.clone()), ..query.clone() }.href();
+            format!(r#"<a href="{href}">{m}</a>"#)
+        })
+        .collect::<Vec<_>>()
+        .join(" | ");
+    let ranking_links = [(lsr::LsrRanking::Level, "level"), (lsr::LsrRanking::ZScore, "z-score")]
+        .map(|(lsr_ranking, title)| format!(r#"<a href="{}">{title}</a>"#, DashboardsQuery { lsr_ranking, ..query.clone() }.href()))
+        .join(" | ");
+    let lsr_str = match &state.lsr_data {
+        Some(data) => lsr::render(data, query.lsr_ranking).unwrap_or_else(|e| format!("Failed to render LSR data: {}", e)),
+        None => state.lsr_str.clone(),
+    };
     Html(format!(
         r#"
         <!DOCTYPE html>
@@ -229,6 +434,15 @@ async fn dashboards_handler(State(state): State<AppState>) -> Html<String> {
                     bottom: 0;
                     cursor: se-resize;
                 }}
+                table.cftc {{
+                    border-collapse: collapse;
+                    margin-top: 10px;
+                }}
+                table.cftc th, table.cftc td {{
+                    border: 1px solid #ccc;
+                    padding: 2px 6px;
+                    text-align: right;
+                }}
                 .notification {{
                     position: fixed;
                     top: 20px;
@@ -245,17 +459,33 @@ async fn dashboards_handler(State(state): State<AppState>) -> Html<String> {
         </head>
         <body>
             <div id="notification" class="notification"></div>
+            <form id="lsr-search">
+                <input id="lsr-search-pair" placeholder="LSR of pair, e.g. BTCUSDT">
+                <button type="submit">Search</button>
+                <pre id="lsr-search-result" style="margin: 0;"></pre>
+            </form>
+            <div>Market structure of {}</div>
             {}
             <div class="container">
                 <div class="resizable">
+                    Rank by {} | <a href="/lsr">full table</a>
                     <pre style="margin: 0;">{}</pre>
+                    {}
                     <div class="resizer"></div>
                 </div>
                 <div class="resizable">
                     <pre style="margin: 0;">{}</pre>
+                    {}
+                    <a href="/dashboards/cme/report.md" download>Markdown</a>
+                    <a href="/dashboards/cme/report.csv" download>CSV</a>
                     <div class="resizer"></div>
                 </div>
             </div>
+            <div>
+                <a href="/dashboards/cme/net_positions.html" download>Export CFTC net positioning</a>
+                {}
+                {}
+            </div>
             <script>
                 document.querySelectorAll('.resizer').forEach(resizer => {{
                     const resizable = resizer.parentElement;
@@ -288,6 +518,24 @@ async fn dashboards_handler(State(state): State<AppState>) -> Html<String> {
                     }}
                 }});
 
+                document.getElementById('lsr-search').addEventListener('submit', e => {{
+                    e.preventDefault();
+                    const pair = document.getElementById('lsr-search-pair').value.trim().toUpperCase();
+                    const result = document.getElementById('lsr-search-result');
+                    fetch('/api/lsr?pair=' + encodeURIComponent(pair))
+                        .then(response => response.ok ? response.json() : response.text().then(t => Promise.reject(t)))
+                        .then(found => {{
+                            result.textContent = found.map(s =>
+                                `${{s.source}}: ${{s.long.toFixed(2)}} (${{s.change >= 0 ? '+' : ''}}${{s.change.toFixed(2)}}), z ${{s.z_score === null ? '.' : s.z_score.toFixed(2)}}, rank ${{s.rank}}/${{s.of}}`
+                            ).join('\n') + '\n';
+                            const link = document.createElement('a');
+                            link.href = '/lsr/' + encodeURIComponent(pair);
+                            link.textContent = 'history';
+                            result.appendChild(link);
+                        }})
+                        .catch(err => {{ result.textContent = err; }});
+                }});
+
                 // Expose the snapshot function globally
                 window.snapshot = function() {{
                     fetch('/dashboards/snapshot', {{ method: 'POST' }})
@@ -310,30 +558,441 @@ async fn dashboards_handler(State(state): State<AppState>) -> Html<String> {
         </body>
         </html>
         "#,
-        html, state.lsr_str, state.cme_str
+        market_links, html, ranking_links, lsr_str, state.lsr_plot_html, state.cme_str, state.cme_table_html, state.cme_plot_html, state.cme_price_plot_html
     ))
 }
 
+async fn cme_net_positions_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let state = state.dashboards.read().unwrap();
+    (
+        [(header::CONTENT_DISPOSITION, "attachment; filename=\"cftc_net_positions.html\"")],
+        Html(state.cme_plot_html.clone()),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct LsrSearchQuery {
+    pair: String,
+    #[serde(default)]
+    ranking: lsr::LsrRanking,
+}
+
+async fn lsr_search_handler(State(state): State<AppState>, Query(query): Query<LsrSearchQuery>) -> Result<Json<Vec<lsr::PairLsrSummary>>, (StatusCode, String)> {
+    let state = state.dashboards.read().unwrap();
+    let data = state.lsr_data.as_ref().ok_or((StatusCode::SERVICE_UNAVAILABLE, "LSR data not collected yet".to_string()))?;
+    let found = data.lookup(&query.pair, query.ranking);
+    match found.is_empty() {
+        true => Err((StatusCode::NOT_FOUND, format!("No LSR data for {}", query.pair))),
+        false => Ok(Json(found)),
+    }
+}
+
+/// Rows of the full LSR table shown per page
+const LSR_TABLE_PAGE_SIZE: usize = 50;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct LsrTableQuery {
+    #[serde(default)]
+    source: Option<lsr::LsrSource>,
+    #[serde(default)]
+    sort: lsr::LsrColumn,
+    #[serde(default)]
+    desc: bool,
+    /// Substring of the pair name
+    #[serde(default)]
+    pair: String,
+    /// Column `min` and `max` bound, independent of the sorted one
+    #[serde(default)]
+    filter_by: lsr::LsrColumn,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    min: Option<f64>,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    max: Option<f64>,
+    /// Starting from 1
+    #[serde(default)]
+    page: Option<usize>,
+}
+impl LsrTableQuery {
+    fn href(&self) -> String {
+        format!("/lsr?{}", serde_urlencoded::to_string(self).unwrap())
+    }
+}
+
+/// Left-empty form inputs are submitted as empty strings
+fn empty_as_none<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    let s = Option::<String>::deserialize(deserializer)?;
+    match s.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(s) => s.parse().map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
+async fn lsr_table_handler(State(state): State<AppState>, Query(query): Query<LsrTableQuery>) -> Result<Html<String>, (StatusCode, String)> {
+    let state = state.dashboards.read().unwrap();
+    let data = state.lsr_data.as_ref().ok_or((StatusCode::SERVICE_UNAVAILABLE, "LSR data not collected yet".to_string()))?;
+    let source = query.source.unwrap_or(lsr::LsrSource::GlobalAccounts);
+
+    let rows = data
+        .table(source, query.sort, query.desc, &query.pair)
+        .into_iter()
+        .filter(|row| match row.value(query.filter_by) {
+            Some(v) => query.min.is_none_or(|min| v >= min) && query.max.is_none_or(|max| v <= max),
+            None => query.min.is_none() && query.max.is_none(),
+        })
+        .collect::<Vec<_>>();
+    let pages = rows.len().div_ceil(LSR_TABLE_PAGE_SIZE).max(1);
+    let page = query.page.unwrap_or(1).clamp(1, pages);
+
+    let header = lsr::LsrColumn::ALL
+        .iter()
+        .map(|column| {
+            let href = LsrTableQuery {
+                sort: *column,
+                desc: *column == query.sort && !query.desc,
+                page: None,
+                ..query.clone()
+            }
+            .href();
+            let arrow = match (*column == query.sort, query.desc) {
+                (false, _) => "",
+                (true, false) => " &#9650;",
+                (true, true) => " &#9660;",
+            };
+            format!(r#"<th><a href="{href}">{}</a>{arrow}</th>"#, column.title())
+        })
+        .collect::<String>();
+    let fmt = |v: Option<f64>| v.map_or(".".to_string(), |v| format!("{v:.2}"));
+    let body = rows
+        .iter()
+        .skip((page - 1) * LSR_TABLE_PAGE_SIZE)
+        .take(LSR_TABLE_PAGE_SIZE)
+        .map(|row| {
+            format!(
+                r#"<tr><td><a href="/lsr/{pair}">{pair}</a></td><td>{:.2}</td><td>{:+.2}</td><td>{}</td><td>{}</td></tr>"#,
+                row.long,
+                row.change,
+                fmt(row.z_score),
+                fmt(row.price_change),
+                pair = row.pair
+            )
+        })
+        .collect::<String>();
+    let page_links = (1..=pages)
+        .map(|p| match p == page {
+            true => p.to_string(),
+            false => format!(r#"<a href="{}">{p}</a>"#, LsrTableQuery { page: Some(p), ..query.clone() }.href()),
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    let source_options = lsr::LsrSource::ALL
+        .iter()
+        .map(|s| {
+            let value = serde_json::to_value(s).unwrap();
+            let selected = if *s == source { " selected" } else { "" };
+            format!(r#"<option value="{}"{selected}>{}</option>"#, value.as_str().unwrap(), s.title())
+        })
+        .collect::<String>();
+    let filter_options = lsr::LsrColumn::ALL
+        .iter()
+        .filter(|c| **c != lsr::LsrColumn::Pair)
+        .map(|c| {
+            let value = serde_json::to_value(c).unwrap();
+            let selected = if *c == query.filter_by { " selected" } else { "" };
+            format!(r#"<option value="{}"{selected}>{}</option>"#, value.as_str().unwrap(), c.title())
+        })
+        .collect::<String>();
+    let bound = |v: Option<f64>| v.map_or(String::new(), |v| v.to_string());
+
+    Ok(Html(format!(
+        r#"
+        <!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta charset="UTF-8">
+            <meta name="viewport" content="width=device-width, initial-scale=1.0">
+            <link rel="icon" type="image/jpg" href="/assets/me.jpg">
+            <title>LSR</title>
+            <style>
+                table.lsr {{
+                    border-collapse: collapse;
+                    margin: 10px 0;
+                }}
+                table.lsr th, table.lsr td {{
+                    border: 1px solid #ccc;
+                    padding: 2px 6px;
+                    text-align: right;
+                }}
+            </style>
+        </head>
+        <body>
+            <h1>Long/short ratios on {}</h1>
+            <form method="get" action="/lsr">
+                <select name="source">{source_options}</select>
+                <input name="pair" placeholder="Pair contains" value="{}">
+                <input type="hidden" name="sort" value="{}">
+                <input type="hidden" name="desc" value="{}">
+                <select name="filter_by">{filter_options}</select>
+                <input name="min" placeholder="Min" value="{}">
+                <input name="max" placeholder="Max" value="{}">
+                <button type="submit">Filter</button>
+            </form>
+            <table class="lsr">
+                <tr>{header}</tr>
+                {body}
+            </table>
+            {} pairs, page {page_links}
+        </body>
+        </html>
+        "#,
+        data.market,
+        query.pair.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;"),
+        serde_json::to_value(query.sort).unwrap().as_str().unwrap(),
+        query.desc,
+        bound(query.min),
+        bound(query.max),
+        rows.len(),
+    )))
+}
+
+async fn lsr_pair_handler(State(state): State<AppState>, Path(pair): Path<String>) -> Result<Html<String>, (StatusCode, String)> {
+    let plot = lsr::plot_pair(&pair, &state.limiters).await.map_err(|e| (StatusCode::NOT_FOUND, e.to_string()))?;
+    Ok(Html(plot.to_html()))
+}
+
+async fn cme_assets_handler(State(state): State<AppState>) -> Html<String> {
+    let state = state.dashboards.read().unwrap();
+    Html(format!(
+        r#"
+        <!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta charset="UTF-8">
+            <meta name="viewport" content="width=device-width, initial-scale=1.0">
+            <link rel="icon" type="image/jpg" href="/assets/me.jpg">
+            <title>CFTC Cross-Asset</title>
+            <style>
+                table.cftc {{
+                    border-collapse: collapse;
+                }}
+                table.cftc th, table.cftc td {{
+                    border: 1px solid #ccc;
+                    padding: 2px 6px;
+                    text-align: right;
+                }}
+            </style>
+        </head>
+        <body>
+            <h1>CFTC positioning across assets</h1>
+            {}
+        </body>
+        </html>
+        "#,
+        state.cme_assets_html
+    ))
+}
+
+async fn cme_history_handler(State(state): State<AppState>) -> Json<cme::CftcHistory> {
+    Json(state.dashboards.read().unwrap().cftc_history.clone())
+}
+
+async fn cme_latest_handler(State(state): State<AppState>) -> Json<Vec<cme::CftcReport>> {
+    let state = state.dashboards.read().unwrap();
+    Json(state.cftc_history.latest().into_iter().cloned().collect())
+}
+
+async fn cme_report_md_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let state = state.dashboards.read().unwrap();
+    (
+        [
+            (header::CONTENT_TYPE, "text/markdown; charset=utf-8"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"cftc_report.md\""),
+        ],
+        state.cme_table_md.clone(),
+    )
+}
+
+async fn cme_report_csv_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let state = state.dashboards.read().unwrap();
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"cftc_report.csv\""),
+        ],
+        state.cme_table_csv.clone(),
+    )
+}
+
 async fn snapshot_handler(State(state): State<AppState>) -> StatusCode {
     let state = state.dashboards.read().unwrap();
-    match state.persist() {
+    let lsr_data = match &state.lsr_data {
+        Some(data) => data.persist(),
+        // don't leave an older one around to be shown with this snapshot
+        None => match fs::remove_file(share_dir!().join(format!("{}.json", lsr::LsrData::NAME))) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        },
+    };
+    match state.persist().and(lsr_data) {
         Ok(_) => StatusCode::OK,
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
     }
 }
 
+/// How long after the scheduled release to give CFTC before fetching
+const CFTC_RELEASE_GRACE: tokio::time::Duration = tokio::time::Duration::from_secs(5 * 60);
+/// How often to refetch while the new week's report hasn't appeared yet
+const CFTC_RETRY_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(15 * 60);
+
+/// Daily candles Binance returns in one request, bounding how far back the CFTC price overlay goes
+const CFTC_PRICE_MAX_DAYS: u32 = 1000;
+
+/// Refreshes CME data shortly after every weekly CFTC release, retrying until the new week shows up
+async fn update_cme(cot_lookbacks: Vec<usize>, state: Arc<RwLock<DashboardsState>>, limiters: Arc<limiter::Limiters>) {
+    // if the stored history can't be loaded, keep it untouched on disk and only track this run's reports in memory
+    let (mut history, persist) = match cme::CftcHistory::load() {
+        Ok(history) => (history, true),
+        Err(e) => {
+            eprintln!("Not persisting CFTC history this run: {}", e);
+            (cme::CftcHistory::default(), false)
+        }
+    };
+    // show what's stored right away rather than waiting on CFTC
+    let mut displayed_any = match history.series(cme::ReportKind::FuturesOnly).last() {
+        Some(latest) => {
+            let header = format!("Stored CME positions as of {}, fetching the latest...", latest.date);
+            show_cme(&state, &history, &cot_lookbacks, header, &[], None);
+            true
+        }
+        None => false,
+    };
+
+    loop {
+        let fetch = cme::fetch_cftc_positions(&mut history).await;
+        if fetch.is_ok() && persist {
+            if let Err(e) = history.save() {
+                eprintln!("Failed to persist CFTC history: {}", e);
+            }
+        }
+        let latest_futures_only = history.series(cme::ReportKind::FuturesOnly).last().map(|r| r.date);
+        let next_release = latest_futures_only.map(|date| cme::release_of(date + chrono::Duration::weeks(1)));
+        // the combined report is fetched alongside, so it's missing the week only if that fetch failed
+        let combined_current = history.series(cme::ReportKind::FuturesAndOptions).last().map(|r| r.date) == latest_futures_only;
+        let fetched = fetch.is_ok() && combined_current;
+
+        match fetch {
+            Ok(update) => {
+                let days = history.first().map_or(0, |r| (chrono::Utc::now() - r.date).num_days()) as u32 + 7;
+                let btc_closes = market_structure::closes("BTCUSDT".try_into().unwrap(), "1d".into(), days.min(CFTC_PRICE_MAX_DAYS).into(), "Binance/Futures".into(), &limiters)
+                    .await
+                    .unwrap_or_else(|e| {
+                        eprintln!("Failed to fetch BTC closes for the CFTC overlay: {}", e);
+                        Vec::new()
+                    });
+
+                let cme_assets_html = cme::cross_asset_html_table(&update.contracts);
+                show_cme(&state, &history, &cot_lookbacks, update.summary, &btc_closes, Some(cme_assets_html));
+                displayed_any = true;
+            }
+            Err(err) => {
+                eprintln!("Failed to update CME data: {}", err);
+                // keep showing the last good report while retrying
+                if !displayed_any {
+                    state.write().unwrap().cme_str = format!("Failed to fetch CME data: {}", err);
+                }
+            }
+        }
+
+        let now = chrono::Utc::now();
+        let sleep_for = match next_release {
+            Some(next_release) if fetched && next_release > now => (next_release - now).to_std().unwrap() + CFTC_RELEASE_GRACE,
+            // nothing fetched this time, either report failed, or the release time has passed and CFTC is late with the new week
+            _ => CFTC_RETRY_INTERVAL,
+        };
+        tokio::time::sleep(sleep_for).await;
+    }
+}
+
+/// Renders every CME panel from `history`, under `header`. Cross-asset data is left as is without `cme_assets_html`.
+fn show_cme(
+    state: &RwLock<DashboardsState>,
+    history: &cme::CftcHistory,
+    cot_lookbacks: &[usize],
+    header: String,
+    btc_closes: &[(chrono::DateTime<chrono::Utc>, f64)],
+    cme_assets_html: Option<String>,
+) {
+    let mut cme_str = header;
+    if let Some(latest) = history.series(cme::ReportKind::FuturesOnly).last() {
+        cme_str.push_str(&format!("\nNext report expected at {}", cme::release_of(latest.date + chrono::Duration::weeks(1))));
+    }
+    if let Some(md) = cme::cot_index_markdown(history, cme::ReportKind::FuturesOnly, cot_lookbacks) {
+        cme_str.push_str(&format!("\n\n{md}"));
+    }
+    if let Some(md) = cme::concentration_markdown(history, cme::ReportKind::FuturesOnly) {
+        cme_str.push_str(&format!("\n\n{md}"));
+    }
+
+    let latest = history.latest();
+    let mut state = state.write().unwrap();
+    state.cme_str = cme_str;
+    state.cme_plot_html = cme::plot_net_positions(history, cme::ReportKind::FuturesOnly).to_html();
+    state.cme_table_html = latest.iter().map(|r| r.to_html_table()).collect::<Vec<_>>().join("\n");
+    state.cme_table_md = latest.iter().map(|r| r.to_markdown_table()).collect::<Vec<_>>().join("\n\n");
+    state.cme_table_csv = cme::reports_to_csv(&latest);
+    state.cme_price_plot_html = cme::plot_positioning_vs_price(history, cme::ReportKind::FuturesOnly, btc_closes).to_html();
+    if let Some(cme_assets_html) = cme_assets_html {
+        state.cme_assets_html = cme_assets_html;
+    }
+    state.cftc_history = history.clone();
+}
+
+/// How often LSR data is recollected, each time extending the per-pair history z-scores are computed over
+const LSR_REFRESH_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(60 * 60);
+
+/// Collects LSR data off the startup path, so the server is up while requests wait for their budget
+async fn update_lsr(range: RequestRange, tf: Timeframe, state: Arc<RwLock<DashboardsState>>, limiters: Arc<limiter::Limiters>) {
+    loop {
+        let lsr_data = lsr::get(tf, range, &limiters).await;
+        {
+            let mut state = state.write().unwrap();
+            match lsr_data {
+                Ok(data) => state.set_lsr_data(data),
+                Err(e) => {
+                    eprintln!("Failed to update LSR data: {}", e);
+                    // keep showing the last collected data while retrying
+                    if state.lsr_data.is_none() {
+                        state.lsr_str = format!("Failed to fetch LSR data: {}", e);
+                    }
+                }
+            }
+        }
+        tokio::time::sleep(LSR_REFRESH_INTERVAL).await;
+    }
+}
+
 //TODO; generalize to allow for specifiying all updates with given _frequency_ through this
-async fn update_plot(limit: RequestRange, tf: Timeframe, state: Arc<RwLock<DashboardsState>>) {
-    let m: v_exchanges::AbsMarket = "Binance/Futures".into();
+async fn update_plot(m: v_exchanges::AbsMarket, limit: RequestRange, tf: Timeframe, max_fill_gap: usize, state: Arc<RwLock<DashboardsState>>, limiters: Arc<limiter::Limiters>) {
+    let market = m.to_string();
 
     loop {
-        match market_structure::try_build(limit, tf, m).await {
-            Ok(new_plot) => {
+        match market_structure::try_build(limit, tf, m, max_fill_gap, &limiters).await {
+            Ok(market_structure::MarketStructure { plot: new_plot, candles, report }) => {
+                let html = format!("{}\n<details><summary>Alignment</summary><pre>{}</pre></details>", new_plot.to_html(), report.to_string().replace('&', "&amp;").replace('<', "&lt;"));
                 let mut state = state.write().unwrap();
-                state.plot_html = new_plot.to_html();
+                match state.market_plots.iter_mut().find(|(existing, _)| *existing == market) {
+                    Some((_, plot)) => *plot = html,
+                    None => state.market_plots.push((market.clone(), html)),
+                }
+                let candles = candles.into_iter().map(|(pair, candles)| (pair.to_string(), candles)).collect();
+                state.market_candles.insert(market.clone(), candles);
+                // LSR data of this market was collected without candles, or with the previous hour's
+                if state.lsr_data.as_ref().is_some_and(|data| data.market == market) {
+                    let data = state.lsr_data.take().expect("checked above");
+                    state.set_lsr_data(data);
+                }
             }
             Err(err) => {
-                eprintln!("Failed to update plot: {}", err);
+                eprintln!("Failed to update plot for {market}: {}", err);
             }
         }
         tokio::time::sleep(tokio::time::Duration::from_secs(60 * 60)).await;
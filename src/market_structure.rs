@@ -1,92 +1,216 @@
 use chrono::{DateTime, Utc};
 use futures::future::join_all;
-use plotly::{Plot, Scatter, common::Line};
+use plotly::{
+	Plot, Scatter,
+	common::Line,
+	layout::{Axis, AxisSide},
+};
 use v_exchanges::prelude::*;
 use v_utils::prelude::*;
 
-//TODO: once v_exchanges implements it properly, switch to take in any market
-pub async fn try_build(limit: RequestRange, tf: Timeframe, market: AbsMarket) -> Result<Plot> {
+use crate::limiter::{self, Limiters, RequestLimiter};
+
+/// Everything one market-structure refresh of a market produces
+pub struct MarketStructure {
+	pub plot: Plot,
+	/// Of every pair fetched, unaligned
+	pub candles: HashMap<Pair, RelevantHistoricalData>,
+	pub report: AlignmentReport,
+}
+
+pub async fn try_build(limit: RequestRange, tf: Timeframe, market: AbsMarket, max_fill_gap: usize, limiters: &Limiters) -> Result<MarketStructure> {
+	let limiter = limiters.for_market(market);
 	let mut exchange = market.client();
 	//exchange.client_mut().update_default_option(BinanceOption::RequestConfig { recv_window: Some(5000) });
 	exchange.set_max_tries(3);
 
-	let exch_info = exchange.exchange_info(market).await.unwrap();
+	let exch_info = limiter.run(1, exchange.exchange_info(market)).await.unwrap();
 	let all_pairs = exch_info.usdt_pairs().collect::<Vec<Pair>>();
+	let reference = btc_reference(&all_pairs).ok_or_else(|| eyre!("No BTC pair listed on {market}"))?;
+
+	let (normalized_df, dt_index, report, candles) = collect_data(all_pairs.clone(), tf, limit, Arc::new(exchange), reference, max_fill_gap, limiter).await?;
+	tracing::info!("Aligned {market}: {report}");
+
+	let btcdom = match closes(BTCDOM_PAIR.try_into().unwrap(), tf, limit, "Binance/Futures".into(), limiters).await {
+		Ok(closes) => normalize_onto(&closes, &dt_index),
+		Err(e) => {
+			tracing::warn!("Failed to fetch {BTCDOM_PAIR}: {e}");
+			None
+		}
+	};
+	Ok(MarketStructure {
+		plot: plotly_closes(normalized_df, dt_index, tf, market, reference, &all_pairs, btcdom),
+		candles,
+		report,
+	})
+}
 
-	let (normalized_df, dt_index) = collect_data(all_pairs.clone(), tf, limit, Arc::new(exchange)).await?;
-	Ok(plotly_closes(normalized_df, dt_index, tf, market, &all_pairs))
+/// Binance futures index of BTC dominance over the top alts
+const BTCDOM_PAIR: &str = "BTCDOMUSDT";
+
+/// `closes` at the open times of `dt_index` as ln(p/p0), `None` where they have no candle
+fn normalize_onto(closes: &[(DateTime<Utc>, f64)], dt_index: &[DateTime<Utc>]) -> Option<Vec<Option<f64>>> {
+	let by_time: HashMap<DateTime<Utc>, f64> = closes.iter().copied().collect();
+	let aligned = dt_index.iter().map(|t| by_time.get(t).copied()).collect::<Vec<_>>();
+	let first = aligned.iter().flatten().next().copied()?;
+	Some(aligned.into_iter().map(|close| close.map(|p| (p / first).ln())).collect())
+}
+
+/// The BTC pair of those a market lists, which the others are aligned to and highlighted against
+pub fn btc_reference(pairs: &[Pair]) -> Option<Pair> {
+	pairs.iter().find(|p| p.base().to_string() == "BTC").copied()
 }
 
-pub async fn collect_data(pairs: Vec<Pair>, tf: Timeframe, range: RequestRange, exchange: Arc<Box<dyn Exchange>>) -> Result<(HashMap<Pair, Vec<f64>>, Vec<DateTime<Utc>>)> {
-	//HACK: assumes we're never misaligned here
+/// What aligning the fetched series onto the reference's open times changed
+#[derive(Clone, Debug, Default)]
+pub struct AlignmentReport {
+	/// Pairs with forward-filled gaps, and how many candles were filled
+	pub filled: Vec<(Pair, usize)>,
+	/// Pairs listed partway through the range, and how many leading candles they lack
+	pub late_listed: Vec<(Pair, usize)>,
+	pub dropped: Vec<(Pair, String)>,
+}
+impl std::fmt::Display for AlignmentReport {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let list = |v: &[(Pair, usize)]| v.iter().map(|(pair, n)| format!("{pair} ({n})")).collect::<Vec<_>>().join(", ");
+		write!(f, "filled {} pairs [{}]", self.filled.len(), list(&self.filled))?;
+		write!(f, "; late listed {} pairs [{}]", self.late_listed.len(), list(&self.late_listed))?;
+		let dropped = self.dropped.iter().map(|(pair, reason)| format!("{pair}: {reason}")).collect::<Vec<_>>().join(", ");
+		write!(f, "; dropped {} pairs [{dropped}]", self.dropped.len())
+	}
+}
+
+/// Closes of each pair, normalized and joined on the open times of `reference` by [align]. Pairs whose fetch failed are reported as dropped.
+/// The fetched candles are returned as well, unaligned.
+pub async fn collect_data(
+	pairs: Vec<Pair>,
+	tf: Timeframe,
+	range: RequestRange,
+	exchange: Arc<Box<dyn Exchange>>,
+	reference: Pair,
+	max_fill_gap: usize,
+	limiter: &RequestLimiter,
+) -> Result<(HashMap<Pair, Vec<Option<f64>>>, Vec<DateTime<Utc>>, AlignmentReport, HashMap<Pair, RelevantHistoricalData>)> {
 	let futures = pairs.into_iter().map(|symbol| {
 		let exchange = Arc::clone(&exchange);
 		async move {
-			match get_historical_data(symbol, tf, range, exchange).await {
-				Ok(series) => Ok((symbol, series)),
-				Err(e) => {
-					tracing::warn!("Failed to fetch data for symbol: {}. Error: {}", symbol, e);
-					Err(e)
-				}
+			let result = get_historical_data(symbol, tf, range, exchange, limiter).await;
+			if let Err(e) = &result {
+				tracing::warn!("Failed to fetch data for symbol: {}. Error: {}", symbol, e);
 			}
+			(symbol, result)
 		}
 	});
 
-	let results = join_all(futures).await;
-	let mut data: HashMap<Pair, Vec<f64>> = HashMap::new();
-	let mut dt_index = Vec::new();
-
-	results.into_iter().for_each(|result| {
-		if let Ok((pair, series)) = result {
-			if "BTCUSDT" == pair {
-				dt_index = series.col_open_times;
+	let mut data: HashMap<Pair, RelevantHistoricalData> = HashMap::new();
+	let mut failed = Vec::new();
+	for (pair, result) in join_all(futures).await {
+		match result {
+			Ok(series) => {
+				data.insert(pair, series);
 			}
-			data.insert(pair, series.col_closes);
+			Err(e) => failed.push((pair, format!("fetch failed: {e}"))),
 		}
-	});
-
-	if dt_index.is_empty() {
-		bail!("Failed to fetch data for BTCUSDT, aborting");
 	}
+
+	let dt_index = match data.get(&reference) {
+		Some(series) if !series.col_open_times.is_empty() => series.col_open_times.clone(),
+		_ => bail!("Failed to fetch data for {reference}, aborting"),
+	};
 	tracing::info!("Fetched data for {} pairs", data.len());
 
-	let mut normalized_df: HashMap<Pair, Vec<f64>> = HashMap::new();
-	for (symbol, closes) in data.into_iter() {
-		let first_close: f64 = match closes.first() {
-			Some(v) => *v,
-			None => {
-				eprintln!("Received empty data for: {symbol}");
-				continue;
+	let (aligned_df, mut report) = align(&data, &dt_index, max_fill_gap);
+	report.dropped.extend(failed);
+	report.dropped.sort_by_key(|(pair, _)| pair.to_string());
+	Ok((aligned_df, dt_index, report, data))
+}
+
+/// Closes of each pair, normalized to ln(p/p0) from its first available candle and joined on `dt_index`.
+///
+/// Gaps of up to `max_fill_gap` candles are forward-filled, pairs with longer ones are dropped. Pairs listed partway through keep `None` before their first candle.
+pub fn align(data: &HashMap<Pair, RelevantHistoricalData>, dt_index: &[DateTime<Utc>], max_fill_gap: usize) -> (HashMap<Pair, Vec<Option<f64>>>, AlignmentReport) {
+	let mut report = AlignmentReport::default();
+	let mut aligned_df: HashMap<Pair, Vec<Option<f64>>> = HashMap::new();
+	for (&symbol, series) in data {
+		match align_series(&series.col_open_times, &series.col_closes, dt_index, max_fill_gap) {
+			Ok(aligned) => {
+				if aligned.filled > 0 {
+					report.filled.push((symbol, aligned.filled));
+				}
+				if aligned.leading > 0 {
+					report.late_listed.push((symbol, aligned.leading));
+				}
+				aligned_df.insert(symbol, aligned.normalized);
 			}
-		};
-		let normalized = closes.into_iter().map(|p| (p / first_close).ln()).collect();
-		normalized_df.insert(symbol, normalized);
+			Err(reason) => report.dropped.push((symbol, reason)),
+		}
 	}
+	for v in [&mut report.filled, &mut report.late_listed] {
+		v.sort_by_key(|(pair, _)| pair.to_string());
+	}
+	(aligned_df, report)
+}
+
+/// One pair's closes on the shared index
+#[derive(Clone, Debug, PartialEq)]
+struct AlignedSeries {
+	normalized: Vec<Option<f64>>,
+	/// Candles forward-filled
+	filled: usize,
+	/// Candles missing before the first one
+	leading: usize,
+}
+
+/// Joins (open time, close) onto `dt_index`, or says why it can't be
+fn align_series(open_times: &[DateTime<Utc>], closes: &[f64], dt_index: &[DateTime<Utc>], max_fill_gap: usize) -> std::result::Result<AlignedSeries, String> {
+	let by_time: HashMap<DateTime<Utc>, f64> = open_times.iter().copied().zip(closes.iter().copied()).collect();
+	let raw = dt_index.iter().map(|t| by_time.get(t).copied()).collect::<Vec<_>>();
+
+	let leading = raw.iter().position(Option::is_some).ok_or("no candles at the reference's open times")?;
+	let first_close = raw[leading].expect("found above");
 
-	let mut aligned_df = normalized_df.clone();
-	for (key, closes) in normalized_df.iter() {
-		if closes.len() != dt_index.len() {
-			//HACK: maybe we want to fill the missing fields instead if there are not many of them
-			eprintln!("misaligned: {key}");
-			aligned_df.remove(key).unwrap();
+	let mut normalized = vec![None; leading];
+	let (mut last, mut gap, mut filled) = (first_close, 0, 0);
+	for close in &raw[leading..] {
+		match close {
+			Some(close) => {
+				last = *close;
+				gap = 0;
+			}
+			None => {
+				gap += 1;
+				filled += 1;
+				if gap > max_fill_gap {
+					return Err(format!("gap of more than {max_fill_gap} candles"));
+				}
+			}
 		}
+		normalized.push(Some((last / first_close).ln()));
 	}
+	Ok(AlignedSeries { normalized, filled, leading })
+}
 
-	Ok((normalized_df, dt_index))
+/// (open time, close) of each of `pair`'s candles on `market`
+pub async fn closes(pair: Pair, tf: Timeframe, range: RequestRange, market: AbsMarket, limiters: &Limiters) -> Result<Vec<(DateTime<Utc>, f64)>> {
+	let mut exchange = market.client();
+	exchange.set_max_tries(3);
+
+	let data = get_historical_data(pair, tf, range, Arc::new(exchange), limiters.for_market(market)).await?;
+	Ok(data.col_open_times.into_iter().zip(data.col_closes).collect())
 }
 
 #[allow(unused)]
-#[derive(Clone, Debug, Default, derive_new::new)]
+#[derive(Clone, Debug, Default, derive_new::new, Deserialize, Serialize)]
 pub struct RelevantHistoricalData {
-	col_open_times: Vec<DateTime<Utc>>,
-	col_opens: Vec<f64>,
-	col_highs: Vec<f64>,
-	col_lows: Vec<f64>,
-	col_closes: Vec<f64>,
-	col_volumes: Vec<f64>,
+	pub col_open_times: Vec<DateTime<Utc>>,
+	pub col_opens: Vec<f64>,
+	pub col_highs: Vec<f64>,
+	pub col_lows: Vec<f64>,
+	pub col_closes: Vec<f64>,
+	pub col_volumes: Vec<f64>,
 }
-pub async fn get_historical_data(pair: Pair, tf: Timeframe, range: RequestRange, exchange: Arc<Box<dyn Exchange>>) -> Result<RelevantHistoricalData> {
-	let klines = exchange.klines(pair, tf, range, exchange.source_market()).await?;
+pub async fn get_historical_data(pair: Pair, tf: Timeframe, range: RequestRange, exchange: Arc<Box<dyn Exchange>>, limiter: &RequestLimiter) -> Result<RelevantHistoricalData> {
+	let klines = limiter.run(limiter.klines_weight(), exchange.klines(pair, tf, range, exchange.source_market())).await?;
 
 	let mut open_time = Vec::new();
 	let mut open = Vec::new();
@@ -112,9 +236,20 @@ pub async fn get_historical_data(pair: Pair, tf: Timeframe, range: RequestRange,
 	})
 }
 
-//TODO!!!: provide additional information: 1) BTCDOM, 2) average, 3) correlation, 4) volatility
-pub fn plotly_closes(normalized_closes: HashMap<Pair, Vec<f64>>, dt_index: Vec<DateTime<Utc>>, tf: Timeframe, m: AbsMarket, all_pairs: &[Pair]) -> Plot {
-	let mut performance: Vec<(Pair, f64)> = normalized_closes.iter().map(|(k, v)| (*k, (v[v.len() - 1] - v[0]))).collect();
+//TODO!!!: provide additional information: 1) average, 2) correlation, 3) volatility
+pub fn plotly_closes(
+	normalized_closes: HashMap<Pair, Vec<Option<f64>>>,
+	dt_index: Vec<DateTime<Utc>>,
+	tf: Timeframe,
+	m: AbsMarket,
+	reference: Pair,
+	all_pairs: &[Pair],
+	btcdom: Option<Vec<Option<f64>>>,
+) -> Plot {
+	let mut performance: Vec<(Pair, f64)> = normalized_closes
+		.iter()
+		.filter_map(|(k, v)| Some((*k, v.iter().flatten().last()? - v.iter().flatten().next()?)))
+		.collect();
 	performance.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
 
 	let n_samples = (performance.len() as f64).ln().round() as usize;
@@ -124,10 +259,14 @@ pub fn plotly_closes(normalized_closes: HashMap<Pair, Vec<f64>>, dt_index: Vec<D
 	let mut plot = Plot::new();
 	let hours = (dt_index.first().unwrap().signed_duration_since(dt_index.last().unwrap()) + tf.duration() * 1).num_hours().abs();
 	let title = format!("Last {hours}h of {}/{} pairs on {m}", normalized_closes.len(), all_pairs.len());
-	plot.set_layout(plotly::Layout::new().title(title));
+	let mut layout = plotly::Layout::new().title(title);
+	if btcdom.is_some() {
+		layout = layout.y_axis2(Axis::new().title("BTCDOM").overlaying("y").side(AxisSide::Right));
+	}
+	plot.set_layout(layout);
 
 	let mut add_trace = |name: Pair, width: f64, color: Option<&'static str>, legend: Option<String>| {
-		let y_values: Vec<f64> = normalized_closes.get(&name).unwrap().to_owned();
+		let y_values: Vec<Option<f64>> = normalized_closes.get(&name).unwrap().to_owned();
 		let x_values: Vec<String> = dt_index.iter().map(|dt| dt.to_rfc3339()).collect();
 
 		let mut line = Line::new().width(width);
@@ -145,10 +284,10 @@ pub fn plotly_closes(normalized_closes: HashMap<Pair, Vec<f64>>, dt_index: Vec<D
 		plot.add_trace(trace);
 	};
 
-	let mut contains_btcusdt = false;
+	let mut contains_reference = false;
 	for col_name in normalized_closes.keys() {
-		if &col_name.to_string() == "BTCUSDT" {
-			contains_btcusdt = true;
+		if *col_name == reference {
+			contains_reference = true;
 			continue;
 		}
 		if top.contains(col_name) || bottom.contains(col_name) {
@@ -170,12 +309,104 @@ pub fn plotly_closes(normalized_closes: HashMap<Pair, Vec<f64>>, dt_index: Vec<D
 	for col_name in top.into_iter() {
 		labeled_trace(col_name, None, 2.0, None);
 	}
-	if contains_btcusdt {
-		labeled_trace("BTCUSDT".try_into().unwrap(), Some("~BTC~"), 3.5, Some("gold"));
+	if contains_reference {
+		labeled_trace(reference, Some("~BTC~"), 3.5, Some("gold"));
 	}
 	for col_name in bottom.into_iter().rev() {
 		labeled_trace(col_name, None, 2.0, None);
 	}
 
+	if let Some(btcdom) = btcdom {
+		let x_values: Vec<String> = dt_index.iter().map(|dt| dt.to_rfc3339()).collect();
+		let line = Line::new().width(2.5).color("black").dash(plotly::common::DashType::Dash);
+		plot.add_trace(Scatter::new(x_values, btcdom).mode(plotly::common::Mode::Lines).line(line).name("BTCDOM").y_axis("y2"));
+	}
+
 	plot
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn index(n: i64) -> Vec<DateTime<Utc>> {
+		(0..n).map(|i| DateTime::UNIX_EPOCH + chrono::Duration::minutes(5 * i)).collect()
+	}
+
+	/// Candles at the `present` positions of a 6-candle index, closing at 1, 2, 3...
+	fn series(present: &[usize]) -> (Vec<DateTime<Utc>>, Vec<f64>) {
+		let index = index(6);
+		present.iter().map(|&i| (index[i], (i + 1) as f64)).unzip()
+	}
+
+	#[test]
+	fn complete_series_is_normalized() {
+		let (times, closes) = series(&[0, 1, 2, 3, 4, 5]);
+		let aligned = align_series(&times, &closes, &index(6), 0).unwrap();
+		assert_eq!(aligned.normalized[0], Some(0.0));
+		assert_eq!(aligned.normalized[5], Some(6.0_f64.ln()));
+		assert_eq!((aligned.filled, aligned.leading), (0, 0));
+	}
+
+	#[test]
+	fn leading_gap_kept_as_late_listing() {
+		let (times, closes) = series(&[2, 3, 4, 5]);
+		let aligned = align_series(&times, &closes, &index(6), 0).unwrap();
+		assert_eq!(aligned.normalized[..2], [None, None]);
+		assert_eq!(aligned.normalized[2], Some(0.0));
+		assert_eq!((aligned.filled, aligned.leading), (0, 2));
+	}
+
+	#[test]
+	fn short_gap_forward_filled() {
+		let (times, closes) = series(&[0, 1, 4, 5]);
+		let aligned = align_series(&times, &closes, &index(6), 2).unwrap();
+		assert_eq!(aligned.normalized[2], aligned.normalized[1]);
+		assert_eq!(aligned.normalized[3], aligned.normalized[1]);
+		assert_eq!(aligned.filled, 2);
+	}
+
+	#[test]
+	fn gap_longer_than_limit_dropped() {
+		let (times, closes) = series(&[0, 1, 4, 5]);
+		assert!(align_series(&times, &closes, &index(6), 1).is_err());
+	}
+
+	#[test]
+	fn trailing_gap_filled_up_to_limit() {
+		let (times, closes) = series(&[0, 1, 2, 3]);
+		let aligned = align_series(&times, &closes, &index(6), 2).unwrap();
+		assert_eq!(aligned.normalized[5], Some(4.0_f64.ln()));
+		assert_eq!(aligned.filled, 2);
+		assert!(align_series(&times, &closes, &index(6), 1).is_err());
+	}
+
+	#[test]
+	fn no_overlap_dropped() {
+		assert!(align_series(&[], &[], &index(6), 3).is_err());
+	}
+
+	#[test]
+	fn report_lists_every_outcome() {
+		let pair = |s: &str| -> Pair { s.try_into().unwrap() };
+		let candles = |present: &[usize]| {
+			let (col_open_times, col_closes) = series(present);
+			RelevantHistoricalData {
+				col_open_times,
+				col_closes,
+				..Default::default()
+			}
+		};
+		let data = HashMap::from([
+			(pair("BTCUSDT"), candles(&[0, 1, 2, 3, 4, 5])),
+			(pair("ETHUSDT"), candles(&[0, 1, 3, 4, 5])),
+			(pair("SOLUSDT"), candles(&[3, 4, 5])),
+			(pair("XRPUSDT"), candles(&[0, 5])),
+		]);
+		let (aligned, report) = align(&data, &index(6), 1);
+		assert_eq!(aligned.len(), 3);
+		assert_eq!(report.filled, vec![(pair("ETHUSDT"), 1)]);
+		assert_eq!(report.late_listed, vec![(pair("SOLUSDT"), 3)]);
+		assert_eq!(report.dropped.iter().map(|(p, _)| *p).collect::<Vec<_>>(), vec![pair("XRPUSDT")]);
+	}
+}